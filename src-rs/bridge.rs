@@ -0,0 +1,124 @@
+//! Extension point for the layer that talks to the Swift runtime.
+//!
+//! [`PluginHandleExt`](crate::PluginHandleExt) never calls into Swift directly. Every plugin operation goes
+//! through a [`SwiftBridge`], so the JSON, error and channel plumbing can run against an in-process
+//! implementation on platforms where the Swift package is not available.
+
+use std::{
+  ffi::{c_void, CStr},
+  os::raw::{c_char, c_int, c_ulonglong},
+  panic,
+};
+
+use crate::{desktop::complete_pending_call, PluginInvokeError};
+
+pub use crate::{channel::send_channel_data, desktop::handle_plugin_response};
+
+/// Backend that forwards plugin operations to the Swift side.
+///
+/// Command responses are delivered back with [`handle_plugin_response`] and channel messages with
+/// [`send_channel_data`], mirroring the callbacks the Swift `PluginManager` receives.
+pub trait SwiftBridge: Send + Sync + 'static {
+  /// Registers the plugin instance returned by its Swift init function.
+  ///
+  /// `webview` is the native webview the plugin is loaded into, or null if none exists yet.
+  ///
+  /// # Safety
+  ///
+  /// `plugin` must be the instance returned by the init function of a Swift `Plugin`, and `webview`
+  /// either null or a live `WKWebView`.
+  unsafe fn register_plugin(
+    &self,
+    name: &str,
+    plugin: *const c_void,
+    config: &str,
+    webview: *const c_void,
+  );
+
+  /// Runs a plugin command.
+  ///
+  /// The result must eventually be reported with [`handle_plugin_response`] using the given `id`.
+  fn run_command(&self, id: i32, plugin: &str, command: &str, payload: &str);
+
+  /// Loads a registered plugin into a native webview, calling its `load(webview:)`.
+  ///
+  /// `controller` is the `UIViewController` on iOS and the `NSWindow` on macOS of the webview, or
+  /// null. Plugins present UI from the first controller the Swift runtime receives.
+  ///
+  /// Does nothing by default.
+  ///
+  /// # Safety
+  ///
+  /// `webview` must be a live `WKWebView` and `controller` either null or the live controller of
+  /// that webview. Bridges that never reach Swift may accept null webviews, which is what plugins
+  /// get on platforms without one.
+  unsafe fn load_plugin(&self, name: &str, webview: *const c_void, controller: *const c_void) {
+    let _ = (name, webview, controller);
+  }
+
+  /// Replaces the config of a registered plugin and calls its `onConfigChanged` hook.
+  ///
+  /// Does nothing by default.
+  fn update_config(&self, name: &str, config: &str) {
+    let _ = (name, config);
+  }
+
+  /// Removes a registered plugin, calling its `unload()` hook before the instance is dropped.
+  ///
+  /// Does nothing by default.
+  fn unregister_plugin(&self, name: &str) {
+    let _ = name;
+  }
+}
+
+/// C callback the Swift runtime reports command responses to.
+///
+/// It never unwinds into Swift: unreadable payloads are delivered to the waiting call as errors and
+/// panics raised while handling the response are logged.
+#[cfg_attr(not(any(target_os = "macos", target_os = "ios")), allow(dead_code))]
+pub(crate) extern "C" fn plugin_command_response_handler(
+  id: c_int,
+  success: c_int,
+  payload: *const c_char,
+) {
+  let result = panic::catch_unwind(|| match unsafe { read_payload(payload) } {
+    Ok(payload) => handle_plugin_response(id, success == 1, payload),
+    Err(e) => complete_pending_call(id, Err(e)),
+  });
+
+  if result.is_err() {
+    log::error!("panicked while handling the response of Swift call {id}");
+  }
+}
+
+/// C callback the Swift runtime sends channel data to.
+///
+/// Like [`plugin_command_response_handler`], it never unwinds into Swift.
+#[cfg_attr(not(any(target_os = "macos", target_os = "ios")), allow(dead_code))]
+pub(crate) extern "C" fn send_channel_data_handler(id: c_ulonglong, payload: *const c_char) {
+  let result = panic::catch_unwind(|| match unsafe { read_payload(payload) } {
+    Ok(payload) => send_channel_data(id, payload),
+    Err(e) => log::error!("dropping data sent to channel {id}: {e}"),
+  });
+
+  if result.is_err() {
+    log::error!("panicked while sending data to channel {id}");
+  }
+}
+
+/// Reads a NUL-terminated UTF-8 string received from Swift.
+///
+/// # Safety
+///
+/// `payload` must be null or point to a NUL-terminated string that outlives `'a`.
+unsafe fn read_payload<'a>(payload: *const c_char) -> Result<&'a str, PluginInvokeError> {
+  if payload.is_null() {
+    return Err(PluginInvokeError::InvalidResponse(
+      "received a null payload".into(),
+    ));
+  }
+
+  unsafe { CStr::from_ptr(payload) }
+    .to_str()
+    .map_err(|e| PluginInvokeError::InvalidResponse(format!("payload is not valid UTF-8: {e}")))
+}
@@ -0,0 +1,565 @@
+//! Rust side of the channels Swift plugins send data through.
+//!
+//! Swift's `Channel` decodes from a `__CHANNEL__:<id>` string and sends its data back to Rust with that
+//! id. A [`ChannelArg`] is that string on the Rust side: it keeps the channel registered for as long
+//! as it is alive.
+
+use futures::{channel::mpsc, executor::block_on, Stream, StreamExt};
+use serde::{de::DeserializeOwned, Serialize, Serializer};
+use serde_json::Value as JsonValue;
+use tauri::{
+  ipc::{Channel, InvokeResponseBody, JavaScriptChannelId},
+  Runtime, Webview, WindowEvent,
+};
+
+use std::{
+  collections::HashMap,
+  fmt,
+  marker::PhantomData,
+  mem,
+  pin::Pin,
+  str::FromStr,
+  sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc, Mutex, MutexGuard, OnceLock, PoisonError, Weak,
+  },
+  task::{Context, Poll},
+};
+
+use crate::PluginInvokeError;
+
+/// Prefix of the string a channel is serialized to, followed by its id.
+pub(crate) const CHANNEL_PREFIX: &str = "__CHANNEL__:";
+
+// JavaScript channels are registered under their own `u32` ids, so Rust channels are numbered above
+// that range to never shadow one.
+static CHANNELS_ID: AtomicU64 = AtomicU64::new(1 << 32);
+static CHANNELS: OnceLock<Mutex<HashMap<u64, Channel<serde_json::Value>>>> = OnceLock::new();
+
+static JS_CHANNELS: OnceLock<Mutex<HashMap<u64, Weak<ChannelRegistration>>>> = OnceLock::new();
+static WEBVIEW_CHANNELS: OnceLock<Mutex<HashMap<String, Vec<ChannelArg>>>> = OnceLock::new();
+
+fn channels() -> MutexGuard<'static, HashMap<u64, Channel<serde_json::Value>>> {
+  CHANNELS
+    .get_or_init(Default::default)
+    .lock()
+    .unwrap_or_else(PoisonError::into_inner)
+}
+
+fn js_channels() -> MutexGuard<'static, HashMap<u64, Weak<ChannelRegistration>>> {
+  JS_CHANNELS
+    .get_or_init(Default::default)
+    .lock()
+    .unwrap_or_else(PoisonError::into_inner)
+}
+
+fn webview_channels() -> MutexGuard<'static, HashMap<String, Vec<ChannelArg>>> {
+  WEBVIEW_CHANNELS
+    .get_or_init(Default::default)
+    .lock()
+    .unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Sends a JSON message to the Rust channel with the given id.
+pub fn send_channel_data(id: u64, payload: &str) {
+  // The channel callback must not run under the CHANNELS lock, it may register another channel.
+  let channel = channels().get(&id).cloned();
+  if let Some(channel) = channel {
+    match serde_json::from_str::<serde_json::Value>(payload) {
+      Ok(payload) => {
+        let _ = channel.send(payload);
+      }
+      Err(e) => log::error!("dropping invalid data sent to channel {id}: {e}"),
+    }
+  }
+}
+
+struct ChannelRegistration {
+  id: u64,
+}
+
+impl Drop for ChannelRegistration {
+  fn drop(&mut self) {
+    let mut channels = channels();
+    let mut js_channels = js_channels();
+    if let Some(registration) = js_channels.get(&self.id) {
+      // The JavaScript channel was passed again while this registration was being dropped, so it
+      // is registered anew under the same id.
+      if !std::ptr::eq(registration.as_ptr(), self) {
+        return;
+      }
+      js_channels.remove(&self.id);
+    }
+    channels.remove(&self.id);
+  }
+}
+
+/// A channel registered for a Swift plugin to send data to.
+///
+/// Serializes to the `__CHANNEL__:<id>` string Swift's `Channel` expects, so it can be embedded in a
+/// command payload. Data keeps flowing until this value and all its clones are dropped.
+#[derive(Clone)]
+pub struct ChannelArg {
+  registration: Arc<ChannelRegistration>,
+}
+
+impl ChannelArg {
+  /// The id Swift sends data to.
+  pub fn id(&self) -> u64 {
+    self.registration.id
+  }
+
+  pub(crate) fn downgrade(&self) -> WeakChannelArg {
+    WeakChannelArg(Arc::downgrade(&self.registration))
+  }
+}
+
+/// A [`ChannelArg`] that does not keep the channel registered, so its owner can close it early.
+pub(crate) struct WeakChannelArg(Weak<ChannelRegistration>);
+
+impl WeakChannelArg {
+  pub(crate) fn is_alive(&self) -> bool {
+    self.0.strong_count() > 0
+  }
+
+  /// Unregisters the channel even though [`ChannelArg`]s still refer to it.
+  pub(crate) fn close(&self) {
+    if let Some(registration) = self.0.upgrade() {
+      channels().remove(&registration.id);
+    }
+  }
+}
+
+impl fmt::Debug for ChannelArg {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("ChannelArg").field("id", &self.id()).finish()
+  }
+}
+
+impl Serialize for ChannelArg {
+  fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&format!("{CHANNEL_PREFIX}{}", self.id()))
+  }
+}
+
+/// Unregisters every channel, ending the streams of their receivers.
+pub(crate) fn close_all_channels() {
+  // Dropping the JavaScript channels unregisters them, which needs the CHANNELS lock.
+  let closed = (mem::take(&mut *channels()), mem::take(&mut *webview_channels()));
+  drop(closed);
+}
+
+pub(crate) fn register_channel(channel: Channel<serde_json::Value>) -> ChannelArg {
+  let id = CHANNELS_ID.fetch_add(1, Ordering::Relaxed);
+  channels().insert(id, channel);
+  ChannelArg {
+    registration: Arc::new(ChannelRegistration { id }),
+  }
+}
+
+/// Registers the JavaScript channels passed in an IPC payload so the Swift plugin can send data to them.
+///
+/// Channels keep their JavaScript id, which is also the `channelId` the frontend passes to
+/// `removeListener`. They stay registered while the returned [`ChannelArg`]s are alive, which is
+/// until the command settles unless they are kept with [`keep_js_channels`].
+pub(crate) fn load_js_channels<R: Runtime>(
+  payload: &JsonValue,
+  webview: &Webview<R>,
+) -> Vec<ChannelArg> {
+  js_channel_ids(payload)
+    .into_iter()
+    .map(|(id, js_id)| register_js_channel(id, || js_id.channel_on(webview.clone())))
+    .collect()
+}
+
+/// Keeps JavaScript channels registered until [`release_js_channel`] is called with their id or
+/// the window of `webview` is destroyed, since the frontend does not notify Rust when it drops a
+/// channel.
+pub(crate) fn keep_js_channels<R: Runtime>(webview: &Webview<R>, args: Vec<ChannelArg>) {
+  if args.is_empty() {
+    return;
+  }
+
+  let label = webview.label().to_string();
+  if track_webview_channels(&label, args) {
+    webview.window().on_window_event(move |event| {
+      if let WindowEvent::Destroyed = event {
+        drop_webview_channels(&label);
+      }
+    });
+  }
+}
+
+/// Finds the `__CHANNEL__:<id>` strings in a JavaScript payload.
+fn js_channel_ids(payload: &JsonValue) -> Vec<(u64, JavaScriptChannelId)> {
+  match payload {
+    JsonValue::String(s) => s
+      .strip_prefix(CHANNEL_PREFIX)
+      .and_then(|id| id.parse().ok())
+      .zip(JavaScriptChannelId::from_str(s).ok())
+      .into_iter()
+      .collect(),
+    JsonValue::Array(values) => values.iter().flat_map(js_channel_ids).collect(),
+    JsonValue::Object(map) => map.values().flat_map(js_channel_ids).collect(),
+    _ => Vec::new(),
+  }
+}
+
+/// Registers a channel under a JavaScript channel id, or returns its registration if the channel is
+/// already registered.
+///
+/// The channel is only created when needed: dropping a channel ends it on the JavaScript side.
+fn register_js_channel(
+  id: u64,
+  channel: impl FnOnce() -> Channel<serde_json::Value>,
+) -> ChannelArg {
+  let mut channels = channels();
+  let mut js_channels = js_channels();
+  if let Some(registration) = js_channels.get(&id).and_then(Weak::upgrade) {
+    return ChannelArg { registration };
+  }
+  channels.insert(id, channel());
+  let registration = Arc::new(ChannelRegistration { id });
+  js_channels.insert(id, Arc::downgrade(&registration));
+  ChannelArg { registration }
+}
+
+/// Keeps the channels of a webview registered. Returns whether the webview had none yet.
+fn track_webview_channels(label: &str, args: Vec<ChannelArg>) -> bool {
+  let mut webview_channels = webview_channels();
+  let first = !webview_channels.contains_key(label);
+  webview_channels
+    .entry(label.to_string())
+    .or_default()
+    .extend(args);
+  first
+}
+
+/// Stops keeping the JavaScript channel `id` of the webview labeled `label` registered.
+pub(crate) fn release_js_channel(label: &str, id: u64) {
+  let released: Vec<ChannelArg> = match webview_channels().get_mut(label) {
+    Some(args) => {
+      let (released, kept) = mem::take(args).into_iter().partition(|arg| arg.id() == id);
+      *args = kept;
+      released
+    }
+    None => Vec::new(),
+  };
+  // Unregistering takes the CHANNELS lock, so the channels are dropped after WEBVIEW_CHANNELS is
+  // released.
+  drop(released);
+}
+
+/// Whether a channel is registered under `id`.
+#[cfg(test)]
+pub(crate) fn is_registered(id: u64) -> bool {
+  channels().contains_key(&id)
+}
+
+fn drop_webview_channels(label: &str) {
+  let args = webview_channels().remove(label);
+  drop(args);
+}
+
+pub(crate) fn create_channel<F: Fn(JsonValue) + Send + Sync + 'static>(on_message: F) -> ChannelArg {
+  register_channel(Channel::new(move |body| {
+    let message = match body {
+      InvokeResponseBody::Json(json) => serde_json::from_str(&json)?,
+      InvokeResponseBody::Raw(bytes) => serde_json::from_slice(&bytes)?,
+    };
+    on_message(message);
+    Ok(())
+  }))
+}
+
+pub(crate) fn open_channel<T: DeserializeOwned>() -> (ChannelArg, SwiftChannelReceiver<T>) {
+  let (tx, rx) = mpsc::unbounded();
+  let arg = create_channel(move |message| {
+    let _ = tx.unbounded_send(message);
+  });
+  let receiver = SwiftChannelReceiver {
+    _arg: arg.clone(),
+    rx,
+    _marker: PhantomData,
+  };
+  (arg, receiver)
+}
+
+/// Receives the data a Swift plugin sends through a channel, deserialized as `T`.
+///
+/// Use it as a [`Stream`] or block on [`Self::recv`]. It keeps the channel registered, like a
+/// [`ChannelArg`] clone.
+pub struct SwiftChannelReceiver<T> {
+  _arg: ChannelArg,
+  rx: mpsc::UnboundedReceiver<JsonValue>,
+  _marker: PhantomData<fn() -> T>,
+}
+
+impl<T> fmt::Debug for SwiftChannelReceiver<T> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("SwiftChannelReceiver")
+      .field("id", &self._arg.id())
+      .finish()
+  }
+}
+
+impl<T: DeserializeOwned> SwiftChannelReceiver<T> {
+  /// Blocks until the next message arrives.
+  ///
+  /// Returns `None` once the channel is closed.
+  pub fn recv(&mut self) -> Option<Result<T, PluginInvokeError>> {
+    block_on(self.next())
+  }
+}
+
+impl<T: DeserializeOwned> Stream for SwiftChannelReceiver<T> {
+  type Item = Result<T, PluginInvokeError>;
+
+  fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+    self.rx.poll_next_unpin(cx).map(|message| {
+      message.map(|message| {
+        serde_json::from_value(message).map_err(PluginInvokeError::CannotDeserializeResponse)
+      })
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use serde::Deserialize;
+  use serde_json::json;
+
+  use std::{sync::mpsc, thread, time::Duration};
+
+  fn is_registered(arg: &ChannelArg) -> bool {
+    channels().contains_key(&arg.id())
+  }
+
+  #[test]
+  fn channel_data_reaches_registered_channel() {
+    let (tx, rx) = mpsc::channel();
+    let channel = Channel::new(move |body| {
+      if let InvokeResponseBody::Json(json) = body {
+        tx.send(json).unwrap();
+      }
+      Ok(())
+    });
+    let arg = register_channel(channel);
+
+    send_channel_data(arg.id(), r#"{"progress":0.5}"#);
+
+    let message: JsonValue = serde_json::from_str(&rx.recv().unwrap()).unwrap();
+    assert_eq!(message, json!({ "progress": 0.5 }));
+  }
+
+  #[test]
+  fn created_channel_receives_json() {
+    let (tx, rx) = mpsc::channel();
+    let arg = create_channel(move |message| tx.send(message).unwrap());
+
+    send_channel_data(arg.id(), "[1,2]");
+    assert_eq!(rx.recv().unwrap(), json!([1, 2]));
+  }
+
+  #[test]
+  fn channel_arg_serializes_to_swift_channel_definition() {
+    let arg = create_channel(|_| {});
+    let payload = json!({ "onProgress": arg });
+    assert_eq!(
+      payload,
+      json!({ "onProgress": format!("__CHANNEL__:{}", arg.id()) })
+    );
+  }
+
+  #[test]
+  fn dropping_last_channel_arg_unregisters_channel() {
+    let (tx, rx) = mpsc::channel();
+    let arg = create_channel(move |message| tx.send(message).unwrap());
+    let id = arg.id();
+
+    let clone = arg.clone();
+    drop(arg);
+    assert!(is_registered(&clone));
+
+    drop(clone);
+    send_channel_data(id, "true");
+    assert!(rx.try_recv().is_err());
+  }
+
+  #[test]
+  fn ffi_channel_callback_drops_unreadable_payloads() {
+    use crate::bridge::send_channel_data_handler;
+
+    let (tx, rx) = mpsc::channel();
+    let arg = create_channel(move |_| tx.send(()).unwrap());
+
+    send_channel_data_handler(arg.id(), std::ptr::null());
+    send_channel_data_handler(arg.id(), c"\xff".as_ptr());
+    send_channel_data_handler(arg.id(), c"{not json".as_ptr());
+    assert!(rx.try_recv().is_err());
+
+    send_channel_data_handler(arg.id(), c"true".as_ptr());
+    assert!(rx.try_recv().is_ok());
+  }
+
+  #[test]
+  fn channel_callbacks_can_use_the_channel_registry() {
+    let (tx, rx) = mpsc::channel();
+    let arg = create_channel(move |_| {
+      drop(create_channel(|_| {}));
+      tx.send(()).unwrap();
+    });
+
+    let id = arg.id();
+    thread::spawn(move || send_channel_data(id, "null"));
+    rx.recv_timeout(Duration::from_secs(5))
+      .expect("channel callback deadlocked");
+  }
+
+  #[derive(Debug, PartialEq, Deserialize)]
+  struct Reading {
+    x: f64,
+  }
+
+  #[test]
+  fn receiver_deserializes_channel_data() {
+    let (arg, mut receiver) = open_channel::<Reading>();
+
+    send_channel_data(arg.id(), r#"{"x":1.5}"#);
+    send_channel_data(arg.id(), r#"{"y":0}"#);
+
+    assert_eq!(receiver.recv().unwrap().unwrap(), Reading { x: 1.5 });
+    assert!(matches!(
+      receiver.recv(),
+      Some(Err(PluginInvokeError::CannotDeserializeResponse(_)))
+    ));
+  }
+
+  #[test]
+  fn receiver_is_a_stream() {
+    let (arg, receiver) = open_channel::<Reading>();
+    let id = arg.id();
+
+    thread::spawn(move || {
+      for x in [1.0, 2.0, 3.0] {
+        send_channel_data(id, &json!({ "x": x }).to_string());
+      }
+    });
+
+    let readings: Vec<Reading> = block_on(receiver.take(3).map(Result::unwrap).collect());
+    assert_eq!(
+      readings,
+      vec![Reading { x: 1.0 }, Reading { x: 2.0 }, Reading { x: 3.0 }]
+    );
+  }
+
+  #[test]
+  fn receiver_keeps_channel_registered() {
+    let (arg, receiver) = open_channel::<Reading>();
+    let clone = arg.clone();
+    drop(arg);
+    drop(receiver);
+    assert!(is_registered(&clone));
+
+    let id = clone.id();
+    drop(clone);
+    assert!(!channels().contains_key(&id));
+  }
+
+  #[test]
+  fn finds_nested_js_channel_ids() {
+    let payload = json!({
+      "onProgress": "__CHANNEL__:7",
+      "listeners": [{ "handler": "__CHANNEL__:8" }],
+      "name": "__CHANNEL__:oops",
+      "count": 9,
+    });
+
+    let mut ids: Vec<u64> = js_channel_ids(&payload).into_iter().map(|(id, _)| id).collect();
+    ids.sort();
+    assert_eq!(ids, vec![7, 8]);
+  }
+
+  #[test]
+  fn js_channels_keep_their_id_until_the_webview_is_gone() {
+    let id = 4_000_000_001;
+    let (tx, rx) = mpsc::channel();
+    let arg = register_js_channel(id, || {
+      Channel::new(move |_| {
+        tx.send(()).unwrap();
+        Ok(())
+      })
+    });
+    assert_eq!(arg.id(), id);
+
+    // A channel passed again is not replaced.
+    let again = register_js_channel(id, || unreachable!());
+    drop(arg);
+    assert!(is_registered(&again));
+
+    assert!(track_webview_channels("js-channels", vec![again]));
+    send_channel_data(id, "null");
+    assert!(rx.try_recv().is_ok());
+
+    drop_webview_channels("js-channels");
+    assert!(!channels().contains_key(&id));
+  }
+
+  #[test]
+  fn js_channels_are_released_by_id() {
+    let (kept, released) = (4_000_000_002, 4_000_000_003);
+    let args = [kept, released].map(|id| register_js_channel(id, || Channel::new(|_| Ok(()))));
+    track_webview_channels("released-channels", args.to_vec());
+    drop(args);
+
+    release_js_channel("released-channels", released);
+    assert!(channels().contains_key(&kept));
+    assert!(!channels().contains_key(&released));
+
+    drop_webview_channels("released-channels");
+    assert!(!channels().contains_key(&kept));
+  }
+
+  #[test]
+  fn channel_ids_differing_in_high_bits_are_distinct() {
+    let low = 42;
+    let high = (1 << 32) | low;
+
+    let (low_tx, low_rx) = mpsc::channel();
+    let low_arg = register_js_channel(low, || {
+      Channel::new(move |_| {
+        low_tx.send(()).unwrap();
+        Ok(())
+      })
+    });
+    let (high_tx, high_rx) = mpsc::channel();
+    let high_arg = register_js_channel(high, || {
+      Channel::new(move |_| {
+        high_tx.send(()).unwrap();
+        Ok(())
+      })
+    });
+
+    send_channel_data(high, "null");
+    assert!(high_rx.try_recv().is_ok());
+    assert!(low_rx.try_recv().is_err());
+
+    drop(high_arg);
+    send_channel_data(high, "null");
+    assert!(high_rx.try_recv().is_err());
+    assert!(low_rx.try_recv().is_err());
+    assert!(is_registered(&low_arg));
+  }
+
+  #[test]
+  fn rust_channel_ids_do_not_overlap_javascript_ids() {
+    let arg = create_channel(|_| {});
+    assert!(arg.id() > u64::from(u32::MAX));
+    assert_eq!(
+      serde_json::to_value(&arg).unwrap(),
+      json!(format!("__CHANNEL__:{}", arg.id()))
+    );
+  }
+}
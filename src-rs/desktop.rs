@@ -1,43 +1,133 @@
 use serde::de::DeserializeOwned;
-use swift_rs::SRString;
-use tauri::{ipc::Channel, plugin::PluginApi, AppHandle, Manager, Runtime};
+use tauri::{ipc::Channel, plugin::PluginApi, AppHandle, Manager, RunEvent, Runtime, Webview};
 
 use serde::Serialize;
 use serde_json::Value as JsonValue;
 
-use memoffset::offset_of;
+use futures::{
+  channel::oneshot,
+  executor::block_on,
+  future::{self, Either, FutureExt},
+};
+use futures_timer::Delay;
+
+use crate::{
+  bridge::SwiftBridge,
+  channel::{
+    close_all_channels, create_channel, open_channel, register_channel, ChannelArg,
+    SwiftChannelReceiver,
+  },
+  lifecycle::{CommandBuffer, PluginState, RegisteredPlugin},
+  registry,
+  listener::{listen, listen_stream, ListenerHandle, SwiftEventStream},
+};
 
 use std::{
-  collections::HashMap,
+  collections::{HashMap, HashSet},
+  ffi::c_void,
   fmt,
-  sync::{mpsc::channel, Mutex, OnceLock},
+  future::Future,
+  pin::Pin,
+  sync::{Mutex, MutexGuard, OnceLock, PoisonError},
+  task::{Context, Poll},
+  time::{Duration, Instant},
 };
 
-use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicI32, Ordering};
 
 use std::sync::Arc;
 
-type PluginResponse = Result<serde_json::Value, serde_json::Value>;
+type PluginResponse = Result<serde_json::Value, PluginInvokeError>;
+
+pub(crate) type PendingPluginCallHandler = Box<dyn FnOnce(PluginResponse) + Send + 'static>;
 
-type PendingPluginCallHandler = Box<dyn FnOnce(PluginResponse) + Send + 'static>;
+struct PendingPluginCall {
+  plugin: String,
+  handler: PendingPluginCallHandler,
+}
 
 static PENDING_PLUGIN_CALLS_ID: AtomicI32 = AtomicI32::new(0);
-static PENDING_PLUGIN_CALLS: OnceLock<Mutex<HashMap<i32, PendingPluginCallHandler>>> =
+static SHUTTING_DOWN: AtomicBool = AtomicBool::new(false);
+static PENDING_PLUGIN_CALLS: OnceLock<Mutex<HashMap<i32, PendingPluginCall>>> =
   OnceLock::new();
-static CHANNELS: OnceLock<Mutex<HashMap<u32, Channel<serde_json::Value>>>> = OnceLock::new();
+
+static PLUGINS: OnceLock<Mutex<HashMap<String, Arc<RegisteredPlugin>>>> = OnceLock::new();
+static WEBVIEW_LOADERS: OnceLock<Mutex<HashSet<String>>> = OnceLock::new();
+
+// The map is only ever mutated by single insert/remove calls, so it stays consistent even if a
+// thread panicked while holding the lock.
+fn pending_plugin_calls() -> MutexGuard<'static, HashMap<i32, PendingPluginCall>> {
+  PENDING_PLUGIN_CALLS
+    .get_or_init(Default::default)
+    .lock()
+    .unwrap_or_else(PoisonError::into_inner)
+}
+
+fn plugins() -> MutexGuard<'static, HashMap<String, Arc<RegisteredPlugin>>> {
+  PLUGINS
+    .get_or_init(Default::default)
+    .lock()
+    .unwrap_or_else(PoisonError::into_inner)
+}
+
+fn webview_loaders() -> MutexGuard<'static, HashSet<String>> {
+  WEBVIEW_LOADERS
+    .get_or_init(Default::default)
+    .lock()
+    .unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Registers `plugin` under its name, unless another plugin that is not unloaded already uses it.
+fn claim_name(plugin: &Arc<RegisteredPlugin>) -> Result<(), PluginInvokeError> {
+  let mut plugins = plugins();
+  match plugins.get(plugin.name()) {
+    Some(other) if !Arc::ptr_eq(other, plugin) && other.state() != PluginState::Unloaded => {
+      Err(PluginInvokeError::AlreadyRegistered(plugin.name().to_string()))
+    }
+    _ => {
+      plugins.insert(plugin.name().to_string(), plugin.clone());
+      Ok(())
+    }
+  }
+}
+
+/// Frees the name of `plugin`, unless another plugin claimed it since.
+fn release_name(plugin: &Arc<RegisteredPlugin>) {
+  let mut plugins = plugins();
+  if plugins.get(plugin.name()).is_some_and(|other| Arc::ptr_eq(other, plugin)) {
+    plugins.remove(plugin.name());
+  }
+}
+
+/// Returns the plugin registered as `name`.
+pub(crate) fn registered_plugin(name: &str) -> Option<Arc<RegisteredPlugin>> {
+  plugins().get(name).cloned()
+}
 
 /// Error response from the Kotlin and Swift backends.
-#[derive(Debug, thiserror::Error, Clone, serde::Deserialize)]
+///
+/// Serializes back to the `{ code, message, ...data }` object the plugin rejected the invoke with.
+#[derive(Debug, thiserror::Error, Clone, serde::Deserialize, serde::Serialize)]
 pub struct ErrorResponse<T = ()> {
   /// Error code.
+  #[serde(skip_serializing_if = "Option::is_none")]
   pub code: Option<String>,
   /// Error message.
+  #[serde(skip_serializing_if = "Option::is_none")]
   pub message: Option<String>,
   /// Optional error data.
   #[serde(flatten)]
   pub data: T,
 }
 
+impl ErrorResponse<JsonValue> {
+  /// Deserializes the error data, the fields the plugin rejected the invoke with besides `code` and
+  /// `message`.
+  pub fn parse_data<T: DeserializeOwned>(&self) -> serde_json::Result<T> {
+    T::deserialize(&self.data)
+  }
+}
+
 impl<T> fmt::Display for ErrorResponse<T> {
   fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
     if let Some(code) = &self.code {
@@ -59,225 +149,2008 @@ pub enum PluginInvokeError {
   /// Failed to reach platform webview handle.
   #[error("the webview is unreachable")]
   UnreachableWebview,
+  /// The main thread did not load the plugin into its webview in time.
+  #[error("the main thread did not load the plugin into the webview in time")]
+  WebviewUnresponsive,
+  /// A blocking call on the main thread would wait for a plugin only the main thread can load.
+  #[error("the plugin is not loaded yet and blocking the main thread would keep it from loading")]
+  WouldBlockMainThread,
+  /// The plugin was unloaded.
+  #[error("the plugin was unloaded")]
+  PluginUnloaded,
+  /// Too many commands are waiting for the plugin to load, see [`CommandBuffer::limit`].
+  #[error("{0} commands are already waiting for the plugin to load")]
+  CommandBufferFull(usize),
+  /// The plugin was not loaded in time to run a buffered command, see [`CommandBuffer::timeout`].
+  #[error("the plugin was not loaded within {0:?}")]
+  NotLoaded(Duration),
+  /// No webview has the label the plugin was registered for.
+  #[error("no webview is labeled `{0}`")]
+  WebviewNotFound(String),
+  /// The app is exiting, see [`swift_run_event_handler`].
+  #[error("the app is shutting down")]
+  ShuttingDown,
+  /// Another plugin is registered under the same name and was not unregistered.
+  #[error("a Swift plugin named `{0}` is already registered")]
+  AlreadyRegistered(String),
   /// Error returned from direct mobile plugin invoke.
   #[error(transparent)]
-  InvokeRejected(#[from] ErrorResponse),
+  InvokeRejected(#[from] ErrorResponse<JsonValue>),
   /// Failed to deserialize response.
   #[error("failed to deserialize response: {0}")]
   CannotDeserializeResponse(serde_json::Error),
   /// Failed to serialize request payload.
   #[error("failed to serialize payload: {0}")]
   CannotSerializePayload(serde_json::Error),
+  /// The response could not be read.
+  #[error("invalid response: {0}")]
+  InvalidResponse(String),
+  /// The Swift plugin did not respond in time.
+  #[error("command `{command}` timed out after {elapsed:?}")]
+  Timeout {
+    /// The command that timed out.
+    command: String,
+    /// Time spent waiting for the response.
+    elapsed: Duration,
+  },
+  /// An error raised while running a command, use [`PluginInvokeError::inner`] to match on it.
+  #[error("{context} failed: {source}{}", .context.snippet_suffix())]
+  Command {
+    /// The call that failed.
+    context: CallContext,
+    /// The error the call failed with.
+    source: Box<PluginInvokeError>,
+  },
 }
 
-#[repr(C)]
-pub struct PluginApiRef<R: Runtime, C: DeserializeOwned> {
-  handle: AppHandle<R>,
-  name: &'static str,
-  raw_config: Arc<JsonValue>,
-  config: C,
+impl PluginInvokeError {
+  /// Returns the error without its call context.
+  pub fn inner(&self) -> &Self {
+    match self {
+      Self::Command { source, .. } => source,
+      error => error,
+    }
+  }
+
+  /// Converts into the error without its call context.
+  pub fn into_inner(self) -> Self {
+    match self {
+      Self::Command { source, .. } => *source,
+      error => error,
+    }
+  }
+
+  /// Returns the call the error was raised by, if known.
+  pub fn context(&self) -> Option<&CallContext> {
+    match self {
+      Self::Command { context, .. } => Some(context),
+      _ => None,
+    }
+  }
+
+  /// Returns the error response if the plugin rejected the invoke.
+  pub fn rejection(&self) -> Option<&ErrorResponse<JsonValue>> {
+    match self.inner() {
+      Self::InvokeRejected(response) => Some(response),
+      _ => None,
+    }
+  }
+
+  /// Attaches the call the error was raised by, unless it already has one.
+  pub(crate) fn with_context(self, context: &CallContext) -> Self {
+    match self {
+      error @ Self::Command { .. } => error,
+      error => Self::Command {
+        context: context.clone(),
+        source: Box::new(error),
+      },
+    }
+  }
+}
+
+/// The plugin command a [`PluginInvokeError`] was raised by.
+#[derive(Debug, Clone)]
+pub struct CallContext {
+  plugin: String,
+  command: String,
+  id: Option<i32>,
+  snippet: Option<String>,
+}
+
+impl CallContext {
+  pub(crate) fn new(plugin: &str, command: &str, id: Option<i32>) -> Self {
+    Self {
+      plugin: plugin.to_string(),
+      command: command.to_string(),
+      id,
+      snippet: None,
+    }
+  }
+
+  fn with_id(mut self, id: i32) -> Self {
+    self.id = Some(id);
+    self
+  }
+
+  fn with_snippet(mut self, snippet: Option<String>) -> Self {
+    self.snippet = snippet;
+    self
+  }
+
+  /// The plugin name.
+  pub fn plugin(&self) -> &str {
+    &self.plugin
+  }
+
+  /// The command name.
+  pub fn command(&self) -> &str {
+    &self.command
+  }
+
+  /// The call id, if the command was sent to Swift.
+  pub fn id(&self) -> Option<i32> {
+    self.id
+  }
+
+  /// The offending JSON, if the handle is configured with an [`ErrorSnippet`].
+  pub fn snippet(&self) -> Option<&str> {
+    self.snippet.as_deref()
+  }
+
+  fn snippet_suffix(&self) -> String {
+    self
+      .snippet
+      .as_ref()
+      .map(|snippet| format!(" (json: {snippet})"))
+      .unwrap_or_default()
+  }
+}
+
+impl fmt::Display for CallContext {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "command `{}|{}`", self.plugin, self.command)?;
+    if let Some(id) = self.id {
+      write!(f, " (call {id})")?;
+    }
+    Ok(())
+  }
+}
+
+type Redact = Arc<dyn Fn(&mut JsonValue) + Send + Sync + 'static>;
+
+/// Includes the offending JSON in the errors of a [`PluginHandleExt`].
+///
+/// The JSON is passed through the redaction hook, if any, then truncated to `max_len` bytes.
+#[derive(Clone)]
+pub struct ErrorSnippet {
+  max_len: usize,
+  redact: Option<Redact>,
 }
 
-#[repr(C)]
-pub struct PluginApiExt<R: Runtime, C: DeserializeOwned>(PluginApi<R, C>);
+impl ErrorSnippet {
+  /// Includes up to `max_len` bytes of the offending JSON.
+  pub fn new(max_len: usize) -> Self {
+    Self {
+      max_len,
+      redact: None,
+    }
+  }
+
+  /// Sets a hook that removes sensitive values from the JSON before it is included.
+  pub fn redact<F: Fn(&mut JsonValue) + Send + Sync + 'static>(mut self, redact: F) -> Self {
+    self.redact = Some(Arc::new(redact));
+    self
+  }
+
+  fn render(&self, value: &JsonValue) -> String {
+    let mut snippet = match &self.redact {
+      Some(redact) => {
+        let mut value = value.clone();
+        redact(&mut value);
+        value.to_string()
+      }
+      None => value.to_string(),
+    };
+
+    if snippet.len() > self.max_len {
+      let mut end = self.max_len;
+      while !snippet.is_char_boundary(end) {
+        end -= 1;
+      }
+      snippet.truncate(end);
+      snippet.push('…');
+    }
+    snippet
+  }
+}
+
+impl fmt::Debug for ErrorSnippet {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("ErrorSnippet")
+      .field("max_len", &self.max_len)
+      .field("redact", &self.redact.is_some())
+      .finish()
+  }
+}
 
-impl<R: Runtime, C: DeserializeOwned> From<PluginApi<R, C>> for PluginApiExt<R, C> {
-    fn from(api: PluginApi<R, C>) -> Self {
-        PluginApiExt(api)
+/// Rejections serialize to the plugin's [`ErrorResponse`], other errors to their message.
+///
+/// This also converts the error into a [`tauri::ipc::InvokeError`], so a command returning it rejects
+/// the JavaScript promise with the same value the Swift plugin rejected the invoke with.
+impl Serialize for PluginInvokeError {
+  fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+    match self.rejection() {
+      Some(response) => response.serialize(serializer),
+      None => serializer.serialize_str(&self.to_string()),
     }
+  }
+}
+
+/// Extends the [`PluginApi`] a plugin receives in its setup hook.
+///
+/// `PluginApi` does not expose the plugin name, so it is passed in alongside it. The raw config is
+/// looked up with that name the same way Tauri does before handing the config to the plugin.
+pub struct PluginApiExt<R: Runtime, C: DeserializeOwned> {
+  api: PluginApi<R, C>,
+  name: &'static str,
+  raw_config: Arc<JsonValue>,
 }
 
 impl<R: Runtime, C: DeserializeOwned> PluginApiExt<R, C> {
+  /// Wraps the API of the plugin built with `tauri::plugin::Builder::new(name)`.
+  pub fn new(api: PluginApi<R, C>, name: &'static str) -> Self {
+    let raw_config = api
+      .app()
+      .config()
+      .plugins
+      .0
+      .get(name)
+      .cloned()
+      .unwrap_or_default();
+
+    Self {
+      api,
+      name,
+      raw_config: Arc::new(raw_config),
+    }
+  }
+
   /// Returns the app handle.
   pub fn app(&self) -> &AppHandle<R> {
-    self.0.app()
+    self.api.app()
   }
 
   /// Returns the plugin name.
-  pub fn name(&self) -> &str {    
-    let self_ptr = &self.0 as *const PluginApi<R, C> as *const u8;
-    let offset = offset_of!(PluginApiRef<R, C>, name);
-    
-    let name: &'static str = unsafe {
-        let field_ptr = self_ptr.add(offset) as *const &'static str;
-        *field_ptr
-    };
-    name
+  pub fn name(&self) -> &str {
+    self.name
+  }
+
+  /// Returns the plugin configuration.
+  pub fn config(&self) -> &C {
+    self.api.config()
   }
 
-  /// Returns the raw plugin configuration.
+  /// Returns the raw plugin configuration, as declared in `plugins.<name>` of the Tauri config.
   pub fn raw_config(&self) -> Arc<JsonValue> {
-    let self_ptr = self as *const PluginApiExt<R, C> as *const u8;
-    let offset = offset_of!(PluginApiRef<R, C>, raw_config);
+    self.raw_config.clone()
+  }
+}
+
+/// The webviews a Swift plugin is loaded into when it is registered.
+///
+/// The plugin's `load(webview:)` is called with each of them. Plugins that present UI, such as share
+/// sheets and pickers, attach it to the window of the webview they were loaded into.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum WebviewTarget {
+  /// The webview whose label sorts first.
+  #[default]
+  First,
+  /// The webview labeled `main`, Tauri's default window label.
+  Main,
+  /// The webview with the given label.
+  Label(String),
+  /// Every webview that exists when the plugin is registered.
+  All,
+}
 
-    let rc_ptr = unsafe { self_ptr.add(offset) as *const Arc<JsonValue> };
-    let rc_ref = unsafe { &*rc_ptr };
-    rc_ref.clone()
+impl From<&str> for WebviewTarget {
+  fn from(label: &str) -> Self {
+    Self::Label(label.to_string())
+  }
+}
+
+impl From<String> for WebviewTarget {
+  fn from(label: String) -> Self {
+    Self::Label(label)
+  }
+}
+
+impl WebviewTarget {
+  /// Picks the labels of the targeted webviews among `labels`, sorted.
+  fn select(&self, labels: impl IntoIterator<Item = String>) -> Result<Vec<String>, PluginInvokeError> {
+    let mut labels: Vec<String> = labels.into_iter().collect();
+    labels.sort();
+
+    let label = match self {
+      Self::First => {
+        labels.truncate(1);
+        return Ok(labels);
+      }
+      Self::All => return Ok(labels),
+      Self::Main => "main",
+      Self::Label(label) => label,
+    };
+
+    if labels.iter().any(|l| l == label) {
+      Ok(vec![label.to_string()])
+    } else {
+      Err(PluginInvokeError::WebviewNotFound(label.to_string()))
+    }
   }
 }
 
-#[cfg(any(target_os = "macos", target_os = "ios"))]
 impl<R: Runtime, C: DeserializeOwned> PluginApiExt<R, C> {
-  /// Registers a Swift plugin.
-  pub fn register_swift_plugin(
+  /// Registers a Swift plugin through the given bridge, loading it into the first webview.
+  pub fn register_swift_plugin_with_bridge(
     &self,
-    init_fn: unsafe fn() -> *const std::ffi::c_void,
+    init_fn: unsafe fn() -> *const c_void,
+    bridge: impl SwiftBridge,
   ) -> Result<PluginHandleExt<R>, PluginInvokeError> {
-    if let Some(webview) = self.app().webviews().values().next() {
-      let (tx, rx) = channel();
-      let name = self.name();
-      let config = self.raw_config().clone();
-      let name = name.to_string();
-      let config = serde_json::to_string(&config).unwrap();
-      webview
-        .with_webview(move |w| {
-          unsafe {
-            crate::macos::swift_register_plugin(
-              &SRString::from(name.as_str()),
-              init_fn(),
-              &serde_json::to_string(&config).unwrap().as_str().into(),
-              w.inner() as _,
-            )
-          };
-          tx.send(()).unwrap();
-        })
-        .map_err(|_| PluginInvokeError::UnreachableWebview)?;
-      rx.recv().unwrap();
-    } else {
-      unsafe {
-        crate::macos::swift_register_plugin(
-          &SRString::from(self.name()),
-          init_fn(),
-          &serde_json::to_string(&self.raw_config())
-            .unwrap()
-            .as_str()
-            .into(),
-          std::ptr::null(),
-        )
-      };
+    self.register_swift_plugin_for_with_bridge(WebviewTarget::First, init_fn, bridge)
+  }
+
+  /// Registers a Swift plugin through the given bridge, loading it into the targeted webviews.
+  ///
+  /// If no webview exists yet, Swift loads the plugin into the first webview created, see
+  /// [`swift_webview_ready_handler`]. Commands sent until then are buffered, see [`PluginState`].
+  /// Fails with [`PluginInvokeError::WebviewNotFound`] if [`WebviewTarget::Main`] or
+  /// [`WebviewTarget::Label`] names a webview that does not exist.
+  ///
+  /// The plugin is loaded into webviews on the main thread. Called from the main thread, e.g. in a
+  /// setup hook, that happens inline. Otherwise this waits up to five seconds for the main thread and
+  /// fails with [`PluginInvokeError::WebviewUnresponsive`] if it is busy; use
+  /// [`Self::register_swift_plugin_for_with_bridge_async`] to wait without blocking. On failure the
+  /// plugin is unloaded and its name can be registered again.
+  pub fn register_swift_plugin_for_with_bridge(
+    &self,
+    target: impl Into<WebviewTarget>,
+    init_fn: unsafe fn() -> *const c_void,
+    bridge: impl SwiftBridge,
+  ) -> Result<PluginHandleExt<R>, PluginInvokeError> {
+    let registration = self.start_registration(target.into(), init_fn, Arc::new(bridge))?;
+    if let Err(e) = wait_for_webviews(registration.loaded, is_main_thread(), WEBVIEW_TIMEOUT) {
+      registration.handle.abandon();
+      return Err(e);
+    }
+    Ok(registration.handle)
+  }
+
+  /// Registers a Swift plugin through the given bridge without blocking the calling thread.
+  ///
+  /// The returned future resolves once the plugin is loaded into the targeted webviews, see
+  /// [`Self::register_swift_plugin_for_with_bridge`].
+  pub fn register_swift_plugin_for_with_bridge_async(
+    &self,
+    target: impl Into<WebviewTarget>,
+    init_fn: unsafe fn() -> *const c_void,
+    bridge: impl SwiftBridge,
+  ) -> impl Future<Output = Result<PluginHandleExt<R>, PluginInvokeError>> + Send + 'static {
+    let registration = self.start_registration(target.into(), init_fn, Arc::new(bridge));
+    async move {
+      let registration = registration?;
+      if future::try_join_all(registration.loaded).await.is_err() {
+        registration.handle.abandon();
+        return Err(PluginInvokeError::UnreachableWebview);
+      }
+      Ok(registration.handle)
     }
+  }
+
+  /// Hands the plugin to Swift, dispatching its loading into the targeted webviews to the main thread.
+  ///
+  /// The plugin is reachable by name right away, so commands sent before it is loaded are buffered.
+  fn start_registration(
+    &self,
+    target: WebviewTarget,
+    init_fn: unsafe fn() -> *const c_void,
+    bridge: Arc<dyn SwiftBridge>,
+  ) -> Result<Registration<R>, PluginInvokeError> {
+    let webview_labels = target.select(self.app().webviews().into_keys())?;
+
+    let plugin = RegisteredPlugin::new(self.name(), bridge);
+    claim_name(&plugin)?;
+
+    // Swift's `Plugin.parseConfig` decodes this string, so it must be the config object itself.
+    let config = self.raw_config.to_string();
+    let loaded = match load_plugin(self.app(), &plugin, &webview_labels, init_fn, config) {
+      Ok(loaded) => loaded,
+      Err(e) => {
+        release_name(&plugin);
+        return Err(e);
+      }
+    };
 
-    Ok(PluginHandleExt {
+    let handle = PluginHandleExt {
       name: self.name().to_string(),
       handle: self.app().clone(),
-    })
+      plugin,
+      webview_labels,
+      config: Arc::new(SharedConfig {
+        current: Mutex::new(self.raw_config.clone()),
+        updates: Mutex::new(()),
+      }),
+      timeout: None,
+      error_snippet: None,
+    };
+    registry::track(&handle);
+
+    Ok(Registration { loaded, handle })
   }
 }
 
-pub struct PluginHandleExt<R: Runtime> {
-  name: String,
-  handle: AppHandle<R>,
-}
+#[cfg(any(target_os = "macos", target_os = "ios"))]
+impl<R: Runtime, C: DeserializeOwned> PluginApiExt<R, C> {
+  /// Registers a Swift plugin, loading it into the first webview.
+  pub fn register_swift_plugin(
+    &self,
+    init_fn: unsafe fn() -> *const c_void,
+  ) -> Result<PluginHandleExt<R>, PluginInvokeError> {
+    self.register_swift_plugin_with_bridge(init_fn, crate::macos::NativeBridge)
+  }
 
-impl<R: Runtime> PluginHandleExt<R> {
-  /// Executes the given Swift command.
-  pub fn run_swift_plugin<T: DeserializeOwned>(
+  /// Registers a Swift plugin, loading it into the targeted webviews.
+  ///
+  /// Pass a webview label to bind the plugin to that webview, or a [`WebviewTarget`].
+  pub fn register_swift_plugin_for(
     &self,
-    command: impl AsRef<str>,
-    payload: impl Serialize,
-  ) -> Result<T, PluginInvokeError> {
-    let (tx, rx) = channel();
+    target: impl Into<WebviewTarget>,
+    init_fn: unsafe fn() -> *const c_void,
+  ) -> Result<PluginHandleExt<R>, PluginInvokeError> {
+    self.register_swift_plugin_for_with_bridge(target, init_fn, crate::macos::NativeBridge)
+  }
 
-    run_command(
-      &self.name,
-      &self.handle,
-      command,
-      serde_json::to_value(payload).map_err(PluginInvokeError::CannotSerializePayload)?,
-      move |response| {
-        tx.send(response).unwrap();
-      },
-    )?;
+  /// Registers a Swift plugin without blocking the calling thread, loading it into the first webview.
+  pub fn register_swift_plugin_async(
+    &self,
+    init_fn: unsafe fn() -> *const c_void,
+  ) -> impl Future<Output = Result<PluginHandleExt<R>, PluginInvokeError>> + Send + 'static {
+    self.register_swift_plugin_for_async(WebviewTarget::First, init_fn)
+  }
+
+  /// Registers a Swift plugin without blocking the calling thread, loading it into the targeted
+  /// webviews.
+  pub fn register_swift_plugin_for_async(
+    &self,
+    target: impl Into<WebviewTarget>,
+    init_fn: unsafe fn() -> *const c_void,
+  ) -> impl Future<Output = Result<PluginHandleExt<R>, PluginInvokeError>> + Send + 'static {
+    self.register_swift_plugin_for_with_bridge_async(target, init_fn, crate::macos::NativeBridge)
+  }
+}
+
+/// Hands a new instance of `plugin` to Swift and loads it into the webviews labeled `labels`.
+///
+/// Returns a receiver per webview, completed once the main thread loaded the plugin into it. Fails
+/// with [`PluginInvokeError::WebviewNotFound`] before reaching Swift if one of them was closed.
+fn load_plugin<R: Runtime>(
+  app: &AppHandle<R>,
+  plugin: &Arc<RegisteredPlugin>,
+  labels: &[String],
+  init_fn: unsafe fn() -> *const c_void,
+  config: String,
+) -> Result<Vec<oneshot::Receiver<()>>, PluginInvokeError> {
+  let webviews = labels
+    .iter()
+    .map(|label| {
+      app
+        .get_webview(label)
+        .ok_or_else(|| PluginInvokeError::WebviewNotFound(label.clone()))
+    })
+    .collect::<Result<Vec<_>, _>>()?;
 
-    let response = rx.recv().unwrap();
-    match response {
-      Ok(r) => serde_json::from_value(r).map_err(PluginInvokeError::CannotDeserializeResponse),
-      Err(r) => Err(
-        serde_json::from_value::<ErrorResponse>(r)
-          .map(Into::into)
-          .map_err(PluginInvokeError::CannotDeserializeResponse)?,
-      ),
+  let mut loaded = Vec::new();
+  // Other platforms have no native webview to load the plugin into.
+  let mut webviews = webviews
+    .into_iter()
+    .filter(|_| cfg!(any(target_os = "macos", target_os = "ios")));
+  if let Some(webview) = webviews.next() {
+    let plugin = plugin.clone();
+    loaded.push(with_native_webview(&webview, move |w, _| {
+      // Unloaded if the caller gave up waiting for the main thread and rolled the plugin back.
+      if plugin.state() == PluginState::Unloaded {
+        return;
+      }
+      // `init_fn` creates the Swift plugin and `w` is the webview this closure runs on.
+      unsafe { plugin.bridge().register_plugin(plugin.name(), init_fn(), &config, w) };
+      plugin.mark_loaded();
+    })?);
+  } else {
+    unsafe { plugin.bridge().register_plugin(plugin.name(), init_fn(), &config, std::ptr::null()) };
+    // Other platforms have no native webview to load the plugin into, and without a
+    // `swift_webview_ready_handler` nothing would ever load it.
+    let no_webview = cfg!(not(any(target_os = "macos", target_os = "ios"))) && !labels.is_empty();
+    if no_webview || !webview_loaders().contains(plugin.name()) {
+      plugin.mark_loaded();
     }
   }
+  for webview in webviews {
+    let plugin = plugin.clone();
+    loaded.push(with_native_webview(&webview, move |w, controller| {
+      // `with_native_webview` hands over the live webview and its controller.
+      unsafe { plugin.bridge().load_plugin(plugin.name(), w, controller) }
+    })?);
+  }
+  Ok(loaded)
 }
 
-pub(crate) fn run_command<R: Runtime, C: AsRef<str>, F: FnOnce(PluginResponse) + Send + 'static>(
-  name: &str,
-  _handle: &AppHandle<R>,
-  command: C,
-  payload: serde_json::Value,
-  handler: F,
+/// How long blocking registration waits for the main thread to load the plugin into a webview.
+const WEBVIEW_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A plugin handed to Swift, waiting to be loaded into its webviews.
+struct Registration<R: Runtime> {
+  loaded: Vec<oneshot::Receiver<()>>,
+  handle: PluginHandleExt<R>,
+}
+
+/// Runs `f` with the native webview on the main thread.
+///
+/// The returned receiver completes once `f` ran, and is canceled if the webview is gone before.
+fn with_native_webview<R: Runtime, F: FnOnce(*const c_void, *const c_void) + Send + 'static>(
+  webview: &tauri::Webview<R>,
+  f: F,
+) -> Result<oneshot::Receiver<()>, PluginInvokeError> {
+  let (tx, rx) = oneshot::channel();
+  webview
+    .with_webview(move |w| {
+      f(native_webview(&w), native_controller(&w));
+      let _ = tx.send(());
+    })
+    .map_err(|_| PluginInvokeError::UnreachableWebview)?;
+  Ok(rx)
+}
+
+#[cfg(any(target_os = "macos", target_os = "ios"))]
+unsafe extern "C" {
+  fn pthread_main_np() -> std::os::raw::c_int;
+}
+
+/// Whether this is the thread the Tauri event loop runs on.
+#[cfg(any(target_os = "macos", target_os = "ios"))]
+fn is_main_thread() -> bool {
+  // SAFETY: `pthread_main_np` takes no arguments and only reads the current thread.
+  unsafe { pthread_main_np() != 0 }
+}
+
+/// Whether this is the thread the Tauri event loop runs on.
+#[cfg(not(any(target_os = "macos", target_os = "ios")))]
+fn is_main_thread() -> bool {
+  std::thread::current().name() == Some("main")
+}
+
+/// Blocks until every webview closure of a registration ran.
+///
+/// On the main thread the closures either already ran inline or are queued behind the current task,
+/// so waiting for them would never return.
+fn wait_for_webviews(
+  loaded: Vec<oneshot::Receiver<()>>,
+  on_main_thread: bool,
+  timeout: Duration,
 ) -> Result<(), PluginInvokeError> {
-  use std::{
-    ffi::CStr,
-    os::raw::{c_char, c_int, c_ulonglong},
+  let loaded = future::try_join_all(loaded);
+  let result = if on_main_thread {
+    loaded.now_or_never()
+  } else {
+    match block_on(future::select(loaded, Delay::new(timeout))) {
+      Either::Left((result, _)) => Some(result),
+      Either::Right(_) => None,
+    }
   };
 
-  let id: i32 = PENDING_PLUGIN_CALLS_ID.fetch_add(1, Ordering::Relaxed);
-  PENDING_PLUGIN_CALLS
-    .get_or_init(Default::default)
-    .lock()
-    .unwrap()
-    .insert(id, Box::new(handler));
-
-  unsafe {
-    extern "C" fn plugin_command_response_handler(
-      id: c_int,
-      success: c_int,
-      payload: *const c_char,
-    ) {
-      let payload = unsafe {
-        assert!(!payload.is_null());
-        CStr::from_ptr(payload)
-      };
-
-      if let Some(handler) = PENDING_PLUGIN_CALLS
-        .get_or_init(Default::default)
-        .lock()
-        .unwrap()
-        .remove(&id)
-      {
-        let json = payload.to_str().unwrap();
-        match serde_json::from_str(json) {
-          Ok(payload) => {
-            handler(if success == 1 {
-              Ok(payload)
-            } else {
-              Err(payload)
-            });
-          }
-          Err(err) => {
-            handler(Err(format!("{err}, data: {json}").into()));
-          }
+  match result {
+    Some(Ok(_)) => Ok(()),
+    Some(Err(_)) => Err(PluginInvokeError::UnreachableWebview),
+    None => Err(PluginInvokeError::WebviewUnresponsive),
+  }
+}
+
+/// Creates a run event hook that releases everything waiting on Swift plugins when the app exits.
+///
+/// On [`RunEvent::Exit`], the commands still waiting for a response fail with
+/// [`PluginInvokeError::ShuttingDown`], the channels are closed and new commands are refused with
+/// the same error, so threads blocked on Swift calls can finish before the process ends.
+///
+/// ```rust,ignore
+/// tauri::plugin::Builder::new("myplugin")
+///   .on_event(tauri_swift_runtime::swift_run_event_handler())
+/// ```
+pub fn swift_run_event_handler<R: Runtime>(
+) -> impl FnMut(&AppHandle<R>, &RunEvent) + Send + 'static {
+  |_app, event| {
+    if let RunEvent::Exit = event {
+      shut_down();
+    }
+  }
+}
+
+fn shut_down() {
+  if SHUTTING_DOWN.swap(true, Ordering::SeqCst) {
+    return;
+  }
+  let calls: Vec<_> = pending_plugin_calls().drain().map(|(_, call)| call.handler).collect();
+  close_all_channels();
+  for handler in calls {
+    handler(Err(PluginInvokeError::ShuttingDown));
+  }
+}
+
+/// Creates a webview hook that loads the Swift plugin `plugin` into the first webview created after
+/// it was registered.
+///
+/// Install it on the Tauri plugin builder next to [`swift_invoke_handler`](crate::swift_invoke_handler),
+/// so a plugin registered before any window exists gets its `load(webview:)` call once the first
+/// webview is created. Only `plugin` is loaded, and only if it is not loaded yet. The Swift runtime
+/// also picks up the view controller plugins present UI from, unless it already has one.
+///
+/// Without this hook, a plugin registered before any window exists is never loaded into a webview
+/// and its commands are sent right away.
+///
+/// ```rust,ignore
+/// tauri::plugin::Builder::new("myplugin")
+///   .invoke_handler(tauri_swift_runtime::swift_invoke_handler("myplugin"))
+///   .on_webview_ready(tauri_swift_runtime::swift_webview_ready_handler("myplugin"))
+/// ```
+pub fn swift_webview_ready_handler<R: Runtime>(
+  plugin: impl Into<String>,
+) -> impl Fn(Webview<R>) + Send + Sync + 'static {
+  let name = plugin.into();
+  webview_loaders().insert(name.clone());
+
+  move |webview| {
+    let Some(plugin) = registered_plugin(&name).filter(|p| p.state() == PluginState::Registered)
+    else {
+      return;
+    };
+
+    #[cfg(any(target_os = "macos", target_os = "ios"))]
+    {
+      let result = webview.with_webview(move |w| {
+        // Checked again on the main thread, which runs every load, in case another webview won.
+        if plugin.state() == PluginState::Registered {
+          let bridge = plugin.bridge();
+          // The pointers come from the webview `with_webview` is running on.
+          unsafe { bridge.load_plugin(plugin.name(), native_webview(&w), native_controller(&w)) };
+          plugin.mark_loaded();
         }
+      });
+      if let Err(e) = result {
+        log::warn!("failed to load plugin {name} into webview {}: {e}", webview.label());
       }
     }
+    #[cfg(not(any(target_os = "macos", target_os = "ios")))]
+    {
+      let _ = webview;
+      // Other platforms have no native webview, so only bridges that never reach Swift run here.
+      unsafe { plugin.bridge().load_plugin(plugin.name(), std::ptr::null(), std::ptr::null()) };
+      plugin.mark_loaded();
+    }
+  }
+}
 
-    extern "C" fn send_channel_data_handler(id: c_ulonglong, payload: *const c_char) {
-      let payload = unsafe {
-        assert!(!payload.is_null());
-        CStr::from_ptr(payload)
-      };
-
-      if let Some(channel) = CHANNELS
-        .get_or_init(Default::default)
-        .lock()
-        .unwrap()
-        .get(&(id as u32))
-      {
-        let payload: serde_json::Value = serde_json::from_str(payload.to_str().unwrap()).unwrap();
-        let _ = channel.send(payload);
-      }
+#[cfg(any(target_os = "macos", target_os = "ios"))]
+fn native_webview(webview: &tauri::webview::PlatformWebview) -> *const c_void {
+  webview.inner() as _
+}
+
+#[cfg(target_os = "macos")]
+fn native_controller(webview: &tauri::webview::PlatformWebview) -> *const c_void {
+  webview.ns_window() as _
+}
+
+#[cfg(target_os = "ios")]
+fn native_controller(webview: &tauri::webview::PlatformWebview) -> *const c_void {
+  webview.view_controller() as _
+}
+
+#[cfg(not(any(target_os = "macos", target_os = "ios")))]
+fn native_webview(_webview: &tauri::webview::PlatformWebview) -> *const c_void {
+  std::ptr::null()
+}
+
+#[cfg(not(any(target_os = "macos", target_os = "ios")))]
+fn native_controller(_webview: &tauri::webview::PlatformWebview) -> *const c_void {
+  std::ptr::null()
+}
+
+/// The config the Swift plugin sees, shared by the clones of a handle.
+struct SharedConfig {
+  current: Mutex<Arc<JsonValue>>,
+  // Serializes updates so they reach Swift in the order they are cached, without holding `current`
+  // while the bridge runs the `onConfigChanged` hook, which may read the config.
+  updates: Mutex<()>,
+}
+
+pub struct PluginHandleExt<R: Runtime> {
+  name: String,
+  handle: AppHandle<R>,
+  plugin: Arc<RegisteredPlugin>,
+  webview_labels: Vec<String>,
+  config: Arc<SharedConfig>,
+  timeout: Option<Duration>,
+  error_snippet: Option<ErrorSnippet>,
+}
+
+// Clones share the plugin and its config, while the timeout and error snippet are per handle.
+impl<R: Runtime> Clone for PluginHandleExt<R> {
+  fn clone(&self) -> Self {
+    Self {
+      name: self.name.clone(),
+      handle: self.handle.clone(),
+      plugin: self.plugin.clone(),
+      webview_labels: self.webview_labels.clone(),
+      config: self.config.clone(),
+      timeout: self.timeout,
+      error_snippet: self.error_snippet.clone(),
     }
+  }
+}
 
-    crate::macos::swift_run_plugin_command(
-      id,
-      &name.into(),
-      &command.as_ref().into(),
-      &serde_json::to_string(&payload).unwrap().as_str().into(),
-      crate::macos::PluginMessageCallback(plugin_command_response_handler),
-      crate::macos::ChannelSendDataCallback(send_channel_data_handler),
-    );
+impl<R: Runtime> PluginHandleExt<R> {
+  /// Returns the app handle.
+  pub fn app(&self) -> &AppHandle<R> {
+    &self.handle
   }
 
-  Ok(())
+  /// Registers a channel the Swift plugin can send data to.
+  ///
+  /// Pass the returned [`ChannelArg`] in a command payload where the plugin expects a `Channel`.
+  /// Data is forwarded to `channel` until the [`ChannelArg`] and all its clones are dropped.
+  pub fn register_channel(&self, channel: Channel<serde_json::Value>) -> ChannelArg {
+    self.plugin.track_channel(register_channel(channel))
+  }
+
+  /// Creates a channel that calls `on_message` with the data the Swift plugin sends through it.
+  ///
+  /// See [`Self::register_channel`].
+  pub fn create_channel<F: Fn(serde_json::Value) + Send + Sync + 'static>(
+    &self,
+    on_message: F,
+  ) -> ChannelArg {
+    self.plugin.track_channel(create_channel(on_message))
+  }
+
+  /// Opens a channel whose data is received in Rust as values of type `T`.
+  ///
+  /// Pass the [`ChannelArg`] to the Swift plugin and read the data from the [`SwiftChannelReceiver`].
+  pub fn open_channel<T: DeserializeOwned>(&self) -> (ChannelArg, SwiftChannelReceiver<T>) {
+    let (channel, receiver) = open_channel();
+    (self.plugin.track_channel(channel), receiver)
+  }
+
+  /// Listens to an event the Swift plugin publishes with `trigger`.
+  ///
+  /// `handler` receives the event data until the returned [`ListenerHandle`] is dropped.
+  ///
+  /// Blocks until Swift registered the listener, so like [`Self::run_swift_plugin`] it fails on the
+  /// main thread while the plugin is not loaded yet.
+  pub fn listen<F: Fn(serde_json::Value) + Send + Sync + 'static>(
+    &self,
+    event: impl AsRef<str>,
+    handler: F,
+  ) -> Result<ListenerHandle, PluginInvokeError> {
+    self.ensure_can_block("registerListener", is_main_thread())?;
+    let bridge = self.plugin.bridge().clone();
+    let listener = listen(bridge, &self.name, event.as_ref(), self.timeout, handler)?;
+    self.plugin.track_channel(listener.channel().clone());
+    Ok(listener)
+  }
+
+  /// Listens to an event the Swift plugin publishes with `trigger`, deserializing its data as `T`.
+  ///
+  /// The listener is removed when the returned [`SwiftEventStream`] is dropped.
+  ///
+  /// Blocks until Swift registered the listener, so like [`Self::run_swift_plugin`] it fails on the
+  /// main thread while the plugin is not loaded yet.
+  pub fn listen_stream<T: DeserializeOwned>(
+    &self,
+    event: impl AsRef<str>,
+  ) -> Result<SwiftEventStream<T>, PluginInvokeError> {
+    self.ensure_can_block("registerListener", is_main_thread())?;
+    let bridge = self.plugin.bridge().clone();
+    let stream = listen_stream(bridge, &self.name, event.as_ref(), self.timeout)?;
+    self.plugin.track_channel(stream.channel().clone());
+    Ok(stream)
+  }
+
+  /// Returns the lifecycle state of the plugin.
+  pub fn state(&self) -> PluginState {
+    self.plugin.state()
+  }
+
+  /// Resolves once the plugin is loaded into a webview.
+  ///
+  /// Fails with [`PluginInvokeError::PluginUnloaded`] if it is unloaded first. Commands do not need
+  /// to wait for it, they are buffered until the plugin is loaded.
+  pub fn await_ready(&self) -> impl Future<Output = Result<(), PluginInvokeError>> + Send + 'static {
+    self.plugin.ready()
+  }
+
+  /// Unregisters the Swift plugin.
+  ///
+  /// Commands it has not answered yet fail with [`PluginInvokeError::PluginUnloaded`], like the
+  /// ones sent afterwards. The channels and listeners created through this handle stop receiving
+  /// data, and Swift drops the plugin instance after calling its `unload()` hook. Does nothing if the
+  /// plugin is already unloaded.
+  pub fn unregister(&self) {
+    if self.plugin.mark_unloaded() {
+      self.plugin.bridge().unregister_plugin(&self.name);
+    }
+  }
+
+  /// Replaces the Swift plugin with a new instance created by `init_fn`, e.g. for hot reloading.
+  ///
+  /// The old instance is torn down like in [`Self::unregister`], then the new one is registered with
+  /// the current config and loaded into the same webviews, or into the first webview if none existed
+  /// at registration. Commands sent meanwhile are buffered for the new instance. Also brings back an
+  /// unregistered plugin, unless another plugin was registered under its name since.
+  ///
+  /// Like registration, this waits for the main thread to load the plugin, see
+  /// [`PluginApiExt::register_swift_plugin_for_with_bridge`]. If the new instance cannot be loaded,
+  /// the plugin is left unloaded as if [`Self::unregister`] was called.
+  pub fn reload(&self, init_fn: unsafe fn() -> *const c_void) -> Result<(), PluginInvokeError> {
+    let labels = if self.webview_labels.is_empty() {
+      WebviewTarget::First.select(self.handle.webviews().into_keys())?
+    } else {
+      self.webview_labels.clone()
+    };
+
+    claim_name(&self.plugin)?;
+    self.plugin.mark_reloading();
+    self.plugin.bridge().unregister_plugin(&self.name);
+
+    let config = self.config().to_string();
+    let result = load_plugin(&self.handle, &self.plugin, &labels, init_fn, config)
+      .and_then(|loaded| wait_for_webviews(loaded, is_main_thread(), WEBVIEW_TIMEOUT));
+    self.unregister_on_error(result)
+  }
+
+  /// Undoes a registration whose plugin could not be loaded into its webviews, releasing its name
+  /// and its entry in the app registry.
+  fn abandon(&self) {
+    self.unregister();
+    release_name(&self.plugin);
+    registry::untrack(self);
+  }
+
+  /// Whether both handles reach the same registered plugin.
+  pub(crate) fn same_plugin(&self, other: &Self) -> bool {
+    Arc::ptr_eq(&self.plugin, &other.plugin)
+  }
+
+  /// Unregisters the plugin if loading its new instance failed, so commands are rejected instead of
+  /// buffered for an instance that never loads.
+  fn unregister_on_error(
+    &self,
+    result: Result<(), PluginInvokeError>,
+  ) -> Result<(), PluginInvokeError> {
+    if result.is_err() {
+      self.unregister();
+    }
+    result
+  }
+
+  /// Returns the limits on the commands buffered until the plugin is loaded.
+  pub fn command_buffer(&self) -> CommandBuffer {
+    self.plugin.command_buffer()
+  }
+
+  /// Sets the limits on the commands buffered until the plugin is loaded.
+  ///
+  /// They apply to commands sent from Rust and JavaScript alike. Defaults to
+  /// [`CommandBuffer::default`].
+  pub fn set_command_buffer(&self, buffer: CommandBuffer) {
+    self.plugin.set_command_buffer(buffer);
+  }
+
+  /// Returns the plugin name.
+  pub fn name(&self) -> &str {
+    &self.name
+  }
+
+  /// Returns the labels of the webviews the plugin was loaded into, sorted.
+  ///
+  /// Empty if no webview existed when it was registered.
+  pub fn webview_labels(&self) -> &[String] {
+    &self.webview_labels
+  }
+
+  /// Returns the config the Swift plugin currently sees.
+  ///
+  /// This is the config it was registered with until [`Self::update_config`] replaces it.
+  pub fn config(&self) -> Arc<JsonValue> {
+    self.config.current.lock().unwrap_or_else(PoisonError::into_inner).clone()
+  }
+
+  /// Deserializes the config the Swift plugin currently sees.
+  pub fn parse_config<T: DeserializeOwned>(&self) -> serde_json::Result<T> {
+    T::deserialize(&*self.config())
+  }
+
+  /// Replaces the config of the Swift plugin without registering it again.
+  ///
+  /// The plugin's `parseConfig` returns the new value from then on and its `onConfigChanged` hook is
+  /// called so it can react, e.g. to a rotated API key. Like the registration config, `config` must
+  /// serialize to an object for `parseConfig` to decode it.
+  ///
+  /// [`Self::config`] returns the new value before Swift is notified. Concurrent updates reach Swift
+  /// in the order they are cached, so the hook must not update the config again synchronously.
+  pub fn update_config(&self, config: impl Serialize) -> Result<(), PluginInvokeError> {
+    let config = serde_json::to_value(config).map_err(PluginInvokeError::CannotSerializePayload)?;
+    let encoded = config.to_string();
+
+    let _update = self.config.updates.lock().unwrap_or_else(PoisonError::into_inner);
+    *self.config.current.lock().unwrap_or_else(PoisonError::into_inner) = Arc::new(config);
+    self.plugin.bridge().update_config(&self.name, &encoded);
+    Ok(())
+  }
+
+  /// Returns the default timeout for Swift commands.
+  pub fn timeout(&self) -> Option<Duration> {
+    self.timeout
+  }
+
+  /// Sets the default timeout for Swift commands.
+  ///
+  /// Commands that take longer fail with [`PluginInvokeError::Timeout`]. Defaults to no timeout.
+  pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+    self.timeout = timeout;
+  }
+
+  /// Returns how much of the offending JSON errors include.
+  pub fn error_snippet(&self) -> Option<&ErrorSnippet> {
+    self.error_snippet.as_ref()
+  }
+
+  /// Sets how much of the offending JSON errors include, see [`CallContext::snippet`].
+  ///
+  /// Defaults to none, since responses may contain user data.
+  pub fn set_error_snippet(&mut self, error_snippet: Option<ErrorSnippet>) {
+    self.error_snippet = error_snippet;
+  }
+
+  /// Executes the given Swift command.
+  ///
+  /// Fails with [`PluginInvokeError::WouldBlockMainThread`] on the main thread while the plugin is
+  /// not loaded yet, since the main thread loads it; use [`Self::run_swift_plugin_async`] there.
+  pub fn run_swift_plugin<T: DeserializeOwned>(
+    &self,
+    command: impl AsRef<str>,
+    payload: impl Serialize,
+  ) -> Result<T, PluginInvokeError> {
+    self.ensure_can_block(command.as_ref(), is_main_thread())?;
+    block_on(self.call(command, payload, self.timeout))
+  }
+
+  /// Executes the given Swift command, overriding the default timeout.
+  pub fn run_swift_plugin_with_timeout<T: DeserializeOwned>(
+    &self,
+    command: impl AsRef<str>,
+    payload: impl Serialize,
+    timeout: Duration,
+  ) -> Result<T, PluginInvokeError> {
+    self.ensure_can_block(command.as_ref(), is_main_thread())?;
+    block_on(self.call(command, payload, Some(timeout)))
+  }
+
+  /// Executes the given Swift command without blocking the calling thread.
+  ///
+  /// The returned future resolves when the Swift plugin resolves or rejects the invoke.
+  /// Dropping it stops waiting for the response, which is then discarded.
+  pub fn run_swift_plugin_async<T: DeserializeOwned>(
+    &self,
+    command: impl AsRef<str>,
+    payload: impl Serialize,
+  ) -> impl Future<Output = Result<T, PluginInvokeError>> + Send + 'static {
+    self.call(command, payload, self.timeout)
+  }
+
+  /// Executes the given Swift command without blocking the calling thread, overriding the default timeout.
+  pub fn run_swift_plugin_async_with_timeout<T: DeserializeOwned>(
+    &self,
+    command: impl AsRef<str>,
+    payload: impl Serialize,
+    timeout: Duration,
+  ) -> impl Future<Output = Result<T, PluginInvokeError>> + Send + 'static {
+    self.call(command, payload, Some(timeout))
+  }
+
+  /// Fails if blocking on `command` would wait for the main thread to load the plugin, from the
+  /// main thread itself.
+  fn ensure_can_block(&self, command: &str, on_main_thread: bool) -> Result<(), PluginInvokeError> {
+    if on_main_thread && self.plugin.state() == PluginState::Registered {
+      let context = CallContext::new(&self.name, command, None);
+      return Err(PluginInvokeError::WouldBlockMainThread.with_context(&context));
+    }
+    Ok(())
+  }
+
+  fn call<T: DeserializeOwned>(
+    &self,
+    command: impl AsRef<str>,
+    payload: impl Serialize,
+    timeout: Option<Duration>,
+  ) -> impl Future<Output = Result<T, PluginInvokeError>> + Send + 'static {
+    call_command(
+      &**self.plugin.bridge(),
+      &self.name,
+      command,
+      payload,
+      timeout,
+      self.error_snippet.clone(),
+    )
+  }
+}
+
+/// Runs a command through the bridge and deserializes its response.
+pub(crate) fn call_command<T: DeserializeOwned>(
+  bridge: &dyn SwiftBridge,
+  name: &str,
+  command: impl AsRef<str>,
+  payload: impl Serialize,
+  timeout: Option<Duration>,
+  error_snippet: Option<ErrorSnippet>,
+) -> impl Future<Output = Result<T, PluginInvokeError>> + Send + 'static {
+  let context = CallContext::new(name, command.as_ref(), None);
+  let response = serde_json::to_value(payload)
+    .map_err(PluginInvokeError::CannotSerializePayload)
+    .and_then(|payload| start_command(bridge, name, command, payload))
+    .map(|call| {
+      let context = context.clone().with_id(call.id);
+      (wait_for_response(call, context.clone(), timeout), context)
+    })
+    .map_err(|e| e.with_context(&context));
+
+  async move {
+    let (response, context) = response?;
+    deserialize_response(response.await, &context, error_snippet.as_ref())
+  }
+}
+
+fn deserialize_response<T: DeserializeOwned>(
+  response: PluginResponse,
+  context: &CallContext,
+  error_snippet: Option<&ErrorSnippet>,
+) -> Result<T, PluginInvokeError> {
+  let response = response.map_err(|e| e.with_context(context))?;
+  T::deserialize(&response).map_err(|e| {
+    let snippet = error_snippet.map(|snippet| snippet.render(&response));
+    PluginInvokeError::CannotDeserializeResponse(e)
+      .with_context(&context.clone().with_snippet(snippet))
+  })
+}
+
+/// A Swift command waiting for its response.
+///
+/// Dropping it before the response arrives removes the pending handler, and the command if it is
+/// still buffered.
+struct PendingCall {
+  id: i32,
+  rx: oneshot::Receiver<PluginResponse>,
+}
+
+impl Future for PendingCall {
+  type Output = PluginResponse;
+
+  fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+    Pin::new(&mut self.rx)
+      .poll(cx)
+      .map(|response| {
+        response.unwrap_or_else(|_| {
+          Err(PluginInvokeError::InvalidResponse(
+            "the response handler was dropped".into(),
+          ))
+        })
+      })
+  }
+}
+
+impl Drop for PendingCall {
+  fn drop(&mut self) {
+    let Some(call) = pending_plugin_calls().remove(&self.id) else {
+      return;
+    };
+    if let Some(plugin) = registered_plugin(&call.plugin) {
+      plugin.dequeue(self.id);
+    }
+  }
+}
+
+/// Waits for the response of a pending call, giving up after `timeout`.
+///
+/// On timeout the call is dropped, which removes its handler so a late response from Swift is discarded.
+fn wait_for_response(
+  call: PendingCall,
+  context: CallContext,
+  timeout: Option<Duration>,
+) -> impl Future<Output = PluginResponse> + Send + 'static {
+  let started = Instant::now();
+  let deadline = timeout.map(Delay::new);
+
+  async move {
+    let Some(deadline) = deadline else {
+      return call.await.map_err(|e| e.with_context(&context));
+    };
+
+    match future::select(call, deadline).await {
+      Either::Left((response, _)) => response.map_err(|e| e.with_context(&context)),
+      Either::Right(((), call)) => {
+        drop(call);
+        Err(
+          PluginInvokeError::Timeout {
+            command: context.command.clone(),
+            elapsed: started.elapsed(),
+          }
+          .with_context(&context),
+        )
+      }
+    }
+  }
+}
+
+fn next_call_id() -> i32 {
+  PENDING_PLUGIN_CALLS_ID.fetch_add(1, Ordering::Relaxed)
+}
+
+fn register_pending_call<F: FnOnce(PluginResponse) + Send + 'static>(
+  id: i32,
+  plugin: &str,
+  handler: F,
+) -> Result<(), PluginInvokeError> {
+  let mut calls = pending_plugin_calls();
+  // Checked under the lock, so no call slips in after `shut_down` drained the map.
+  if SHUTTING_DOWN.load(Ordering::SeqCst) {
+    return Err(PluginInvokeError::ShuttingDown);
+  }
+  calls.insert(
+    id,
+    PendingPluginCall {
+      plugin: plugin.to_string(),
+      handler: Box::new(handler),
+    },
+  );
+  Ok(())
+}
+
+/// Removes the calls of `plugin` still waiting for a response, except the ones `keep` selects.
+pub(crate) fn remove_pending_calls(
+  plugin: &str,
+  keep: impl Fn(i32) -> bool,
+) -> Vec<PendingPluginCallHandler> {
+  let mut calls = pending_plugin_calls();
+  let ids: Vec<i32> = calls
+    .iter()
+    .filter(|(id, call)| call.plugin == plugin && !keep(**id))
+    .map(|(id, _)| *id)
+    .collect();
+  ids
+    .into_iter()
+    .filter_map(|id| calls.remove(&id))
+    .map(|call| call.handler)
+    .collect()
+}
+
+fn start_command<C: AsRef<str>>(
+  bridge: &dyn SwiftBridge,
+  name: &str,
+  command: C,
+  payload: serde_json::Value,
+) -> Result<PendingCall, PluginInvokeError> {
+  let (tx, rx) = oneshot::channel();
+  let id = run_command(bridge, name, command, payload, move |response| {
+    let _ = tx.send(response);
+  })?;
+  Ok(PendingCall { id, rx })
+}
+
+/// Starts `command` and hands its response to `handler`.
+///
+/// Errors, whether returned or passed to `handler`, carry the [`CallContext`] of the call.
+pub(crate) fn run_command<C: AsRef<str>, F: FnOnce(PluginResponse) + Send + 'static>(
+  bridge: &dyn SwiftBridge,
+  name: &str,
+  command: C,
+  payload: serde_json::Value,
+  handler: F,
+) -> Result<i32, PluginInvokeError> {
+  let id = next_call_id();
+  let context = CallContext::new(name, command.as_ref(), Some(id));
+  let handler_context = context.clone();
+  register_pending_call(id, name, move |response: PluginResponse| {
+    handler(response.map_err(|e| e.with_context(&handler_context)))
+  })
+  .map_err(|e| e.with_context(&context))?;
+
+  let payload = serde_json::to_string(&payload).unwrap();
+  match registered_plugin(name) {
+    Some(plugin) => {
+      if let Err(e) = plugin.send(id, command.as_ref(), payload) {
+        pending_plugin_calls().remove(&id);
+        return Err(e.with_context(&context));
+      }
+    }
+    None => bridge.run_command(id, name, command.as_ref(), &payload),
+  }
+  Ok(id)
+}
+
+/// Delivers the response of a command started with [`SwiftBridge::run_command`].
+///
+/// `payload` is the JSON the plugin resolved or rejected the invoke with.
+/// Responses for unknown or expired calls are ignored.
+pub fn handle_plugin_response(id: i32, success: bool, payload: &str) {
+  let response = serde_json::from_str(payload)
+    .map_err(PluginInvokeError::CannotDeserializeResponse)
+    .and_then(|payload| {
+      if success {
+        Ok(payload)
+      } else {
+        Err(
+          serde_json::from_value::<ErrorResponse<JsonValue>>(payload)
+            .map(Into::into)
+            .unwrap_or_else(PluginInvokeError::CannotDeserializeResponse),
+        )
+      }
+    });
+
+  complete_pending_call(id, response);
+}
+
+/// Hands the response to the handler waiting on the call `id`, if any.
+pub(crate) fn complete_pending_call(id: i32, response: PluginResponse) {
+  // Release the lock before running the handler: it may start another command,
+  // which needs PENDING_PLUGIN_CALLS to register itself.
+  let call = pending_plugin_calls().remove(&id);
+  if let Some(call) = call {
+    (call.handler)(response);
+  } else if let Err(e) = response {
+    log::warn!("dropping failed response of Swift call {id}, nothing is waiting for it: {e}");
+  } else {
+    log::debug!("dropping response of Swift call {id}, nothing is waiting for it");
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use crate::{
+    mock::MockSwiftPlugin,
+    test_util::{init_null_plugin, mock_app, with_plugin_api, ConfigBridge, EchoBridge, MockApi},
+    SwiftPluginsExt,
+  };
+
+  use futures::executor::block_on;
+  use serde_json::json;
+
+  use std::{
+    sync::{mpsc, Mutex},
+    thread,
+  };
+
+  fn respond(id: i32, success: bool, payload: &str) {
+    handle_plugin_response(id, success, payload);
+  }
+
+  fn is_pending(id: i32) -> bool {
+    pending_plugin_calls()
+      .contains_key(&id)
+  }
+
+  fn assert_send<T: Send + 'static>(_: &T) {}
+
+  fn pending_call() -> PendingCall {
+    let (tx, rx) = oneshot::channel();
+    let id = next_call_id();
+    register_pending_call(id, "test", move |response| {
+      let _ = tx.send(response);
+    })
+    .unwrap();
+    PendingCall { id, rx }
+  }
+
+  fn context(command: &str) -> CallContext {
+    CallContext::new("test", command, None)
+  }
+
+  fn decode<T: DeserializeOwned>(response: PluginResponse) -> Result<T, PluginInvokeError> {
+    deserialize_response(response, &context("decode"), None)
+  }
+
+  #[test]
+  fn pending_call_resolves_from_response_handler() {
+    let call = pending_call();
+    let id = call.id;
+
+    let swift = thread::spawn(move || respond(id, true, r#"{"value":42}"#));
+    let response: JsonValue = block_on(async move { decode(call.await) }).unwrap();
+    swift.join().unwrap();
+
+    assert_eq!(response, json!({ "value": 42 }));
+    assert!(!is_pending(id));
+  }
+
+  #[test]
+  fn pending_call_maps_rejection() {
+    let call = pending_call();
+    respond(call.id, false, r#"{"code":"denied","message":"no access"}"#);
+
+    match block_on(async move { decode::<JsonValue>(call.await).map_err(PluginInvokeError::into_inner) }) {
+      Err(PluginInvokeError::InvokeRejected(e)) => {
+        assert_eq!(e.code.as_deref(), Some("denied"));
+        assert_eq!(e.message.as_deref(), Some("no access"));
+      }
+      other => panic!("unexpected response: {other:?}"),
+    }
+  }
+
+  #[test]
+  fn rejection_keeps_error_data() {
+    #[derive(Debug, PartialEq, serde::Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    struct Busy {
+      retry_after: u64,
+    }
+
+    let call = pending_call();
+    respond(
+      call.id,
+      false,
+      r#"{"code":"EBUSY","message":"busy","retryAfter":5}"#,
+    );
+
+    match block_on(call) {
+      Err(PluginInvokeError::InvokeRejected(e)) => {
+        assert_eq!(e.data, json!({ "retryAfter": 5 }));
+        assert_eq!(e.parse_data::<Busy>().unwrap(), Busy { retry_after: 5 });
+        assert!(e.parse_data::<String>().is_err());
+      }
+      other => panic!("unexpected response: {other:?}"),
+    }
+  }
+
+  #[test]
+  fn errors_convert_to_invoke_errors_losslessly() {
+    use tauri::ipc::InvokeError;
+
+    let call = pending_call();
+    let payload = json!({ "code": "EBUSY", "message": "busy", "retryAfter": 5 });
+    respond(call.id, false, &payload.to_string());
+    let error = block_on(call).unwrap_err();
+    assert_eq!(InvokeError::from(error).0, payload);
+
+    let call = pending_call();
+    respond(call.id, false, r#"{"message":"no code"}"#);
+    let error = block_on(call).unwrap_err();
+    assert_eq!(InvokeError::from(error).0, json!({ "message": "no code" }));
+
+    let error = PluginInvokeError::InvalidResponse("received a null payload".into());
+    assert_eq!(
+      InvokeError::from(error).0,
+      json!("invalid response: received a null payload")
+    );
+  }
+
+  #[test]
+  fn dropped_pending_call_discards_late_response() {
+    let call = pending_call();
+    let id = call.id;
+    assert!(is_pending(id));
+
+    drop(call);
+    assert!(!is_pending(id));
+
+    respond(id, true, "null");
+  }
+
+  #[test]
+  fn pending_call_future_is_send() {
+    let future = async move { decode::<JsonValue>(pending_call().await) };
+    assert_send(&future);
+  }
+
+  #[test]
+  fn pending_call_times_out_and_discards_late_response() {
+    let call = pending_call();
+    let id = call.id;
+
+    let response = block_on(wait_for_response(
+      call,
+      context("slow"),
+      Some(Duration::from_millis(20)),
+    ));
+
+    let error = response.unwrap_err();
+    assert_eq!(error.context().unwrap().command(), "slow");
+    match error.into_inner() {
+      PluginInvokeError::Timeout { command, elapsed } => {
+        assert_eq!(command, "slow");
+        assert!(elapsed >= Duration::from_millis(20));
+      }
+      other => panic!("unexpected error: {other:?}"),
+    }
+    assert!(!is_pending(id));
+
+    respond(id, true, "null");
+  }
+
+  #[test]
+  fn pending_call_resolves_before_timeout() {
+    let call = pending_call();
+    respond(call.id, true, "true");
+
+    let response = block_on(wait_for_response(
+      call,
+      context("fast"),
+      Some(Duration::from_secs(5)),
+    ));
+    assert_eq!(response.unwrap(), json!(true));
+  }
+
+  #[test]
+  fn bridge_round_trips_payload() {
+    let call = start_command(&EchoBridge, "echo", "ping", json!({ "n": 1 })).unwrap();
+    let response: JsonValue = block_on(async move { decode(call.await) }).unwrap();
+    assert_eq!(response, json!({ "n": 1 }));
+  }
+
+  #[test]
+  fn bridge_rejection_becomes_error_response() {
+    let call = start_command(&EchoBridge, "echo", "fail", json!("boom")).unwrap();
+
+    match block_on(async move { decode::<JsonValue>(call.await).map_err(PluginInvokeError::into_inner) }) {
+      Err(PluginInvokeError::InvokeRejected(e)) => {
+        assert_eq!(e.code.as_deref(), Some("echo"));
+        assert_eq!(e.message.as_deref(), Some("\"boom\""));
+      }
+      other => panic!("unexpected response: {other:?}"),
+    }
+  }
+
+  #[test]
+  fn ffi_callback_reports_unreadable_payloads() {
+    use crate::bridge::plugin_command_response_handler;
+
+    let null = pending_call();
+    plugin_command_response_handler(null.id, 1, std::ptr::null());
+    assert!(matches!(
+      block_on(null),
+      Err(PluginInvokeError::InvalidResponse(_))
+    ));
+
+    let not_utf8 = pending_call();
+    plugin_command_response_handler(not_utf8.id, 1, c"\xff\xfe".as_ptr());
+    assert!(matches!(
+      block_on(not_utf8),
+      Err(PluginInvokeError::InvalidResponse(_))
+    ));
+
+    let not_json = pending_call();
+    plugin_command_response_handler(not_json.id, 1, c"{not json".as_ptr());
+    assert!(matches!(
+      block_on(not_json),
+      Err(PluginInvokeError::CannotDeserializeResponse(_))
+    ));
+
+    let bad_error = pending_call();
+    plugin_command_response_handler(bad_error.id, 0, c"42".as_ptr());
+    assert!(matches!(
+      block_on(bad_error),
+      Err(PluginInvokeError::CannotDeserializeResponse(_))
+    ));
+  }
+
+  #[test]
+  fn ffi_callback_contains_handler_panics() {
+    use crate::bridge::plugin_command_response_handler;
+
+    let id = next_call_id();
+    register_pending_call(id, "test", |_| panic!("handler failure")).unwrap();
+    plugin_command_response_handler(id, 1, c"null".as_ptr());
+    assert!(!is_pending(id));
+
+    // nothing is waiting for these anymore
+    plugin_command_response_handler(id, 1, std::ptr::null());
+    plugin_command_response_handler(id, 1, c"null".as_ptr());
+
+    let call = pending_call();
+    respond(call.id, true, "1");
+    assert_eq!(block_on(call).unwrap(), json!(1));
+  }
+
+  #[test]
+  fn response_handlers_can_start_nested_commands() {
+    let (tx, rx) = mpsc::channel();
+
+    // EchoBridge responds synchronously, so every level runs inside the previous response handler.
+    thread::spawn(move || {
+      run_command(&EchoBridge, "echo", "outer", json!(1), move |outer| {
+        run_command(&EchoBridge, "echo", "inner", json!(2), move |inner| {
+          let nested = start_command(&EchoBridge, "echo", "innermost", json!(3)).unwrap();
+          tx.send((outer.unwrap(), inner.unwrap(), block_on(nested).unwrap()))
+            .unwrap();
+        })
+        .unwrap();
+      })
+      .unwrap();
+    });
+
+    let responses = rx
+      .recv_timeout(Duration::from_secs(5))
+      .expect("nested command deadlocked");
+    assert_eq!(responses, (json!(1), json!(2), json!(3)));
+  }
+
+  #[test]
+  fn command_errors_name_the_plugin_command_and_call() {
+    let error = block_on(call_command::<JsonValue>(
+      &EchoBridge,
+      "echo",
+      "fail",
+      "boom",
+      None,
+      None,
+    ))
+    .unwrap_err();
+
+    let context = error.context().unwrap();
+    assert_eq!(context.plugin(), "echo");
+    assert_eq!(context.command(), "fail");
+    let id = context.id().unwrap();
+    assert_eq!(error.rejection().unwrap().code.as_deref(), Some("echo"));
+    assert_eq!(
+      error.to_string(),
+      format!("command `echo|fail` (call {id}) failed: [echo] - \"boom\"")
+    );
+
+    let error = block_on(call_command::<JsonValue>(
+      &EchoBridge,
+      "echo",
+      "ping",
+      HashMap::from([((), ())]),
+      None,
+      None,
+    ))
+    .unwrap_err();
+    assert_eq!(error.context().unwrap().id(), None);
+    assert!(matches!(
+      error.inner(),
+      PluginInvokeError::CannotSerializePayload(_)
+    ));
+  }
+
+  #[test]
+  fn deserialize_errors_include_a_redacted_truncated_snippet() {
+    let response = json!({ "token": "secret", "lat": "north", "zpadding": "x".repeat(100) });
+    let snippet = ErrorSnippet::new(40).redact(|value| value["token"] = json!("[redacted]"));
+
+    #[derive(Debug, serde::Deserialize)]
+    #[allow(dead_code)]
+    struct Location {
+      lat: f64,
+    }
+
+    let error =
+      deserialize_response::<Location>(Ok(response.clone()), &context("get"), Some(&snippet))
+        .unwrap_err();
+    let snippet = error.context().unwrap().snippet().unwrap();
+    assert!(!snippet.contains("secret"));
+    assert!(snippet.contains("[redacted]"));
+    assert!(snippet.ends_with('…'));
+    assert!(snippet.len() <= 40 + '…'.len_utf8());
+    assert!(error.to_string().contains(snippet));
+    assert!(matches!(
+      error.inner(),
+      PluginInvokeError::CannotDeserializeResponse(_)
+    ));
+
+    let error = deserialize_response::<Location>(Ok(response), &context("get"), None).unwrap_err();
+    assert_eq!(error.context().unwrap().snippet(), None);
+  }
+
+  /// Registers a plugin on a mock app and returns what its setup hook saw, as
+  /// `(PluginApi::config, PluginApiExt::raw_config, PluginApiExt::name)`.
+  fn plugin_api_ext(
+    name: &'static str,
+    config: Option<JsonValue>,
+  ) -> (JsonValue, Arc<JsonValue>, String) {
+    with_plugin_api(name, config, |api: MockApi<JsonValue>| {
+      (api.config().clone(), api.raw_config(), api.name().to_string())
+    })
+  }
+
+  #[derive(Debug, Clone, PartialEq, serde::Deserialize)]
+  #[serde(rename_all = "camelCase")]
+  struct DemoConfig {
+    api_key: String,
+    retries: u32,
+  }
+
+  #[test]
+  fn swift_receives_the_declared_config_object() {
+    let declared = json!({ "apiKey": "secret", "retries": 3, "nested": { "list": [1, 2] } });
+    let registered = Arc::new(Mutex::new(Vec::new()));
+
+    let bridge = ConfigBridge(registered.clone());
+    let config = with_plugin_api(
+      "configdemo",
+      Some(declared.clone()),
+      move |api: MockApi<DemoConfig>| {
+        api.register_swift_plugin_with_bridge(init_null_plugin, bridge).unwrap();
+        api.config().clone()
+      },
+    );
+    assert_eq!(
+      config,
+      DemoConfig {
+        api_key: "secret".into(),
+        retries: 3
+      }
+    );
+
+    let registered = registered.lock().unwrap();
+    let (name, config) = &registered[0];
+    assert_eq!(name, "configdemo");
+    // What Swift's JSONDecoder parses in `Plugin.parseConfig`.
+    let decoded: JsonValue = serde_json::from_str(config).unwrap();
+    assert_eq!(decoded, declared);
+    let decoded: DemoConfig = serde_json::from_str(config).unwrap();
+    assert_eq!(decoded.api_key, "secret");
+  }
+
+  #[test]
+  fn plugin_api_ext_sees_the_config_tauri_passes_to_the_plugin() {
+    let config = json!({ "greeting": "hi", "nested": { "n": 1 } });
+    let (tauri_config, raw_config, name) = plugin_api_ext("configured", Some(config.clone()));
+    assert_eq!(name, "configured");
+    assert_eq!(*raw_config, tauri_config);
+    assert_eq!(*raw_config, config);
+
+    let (tauri_config, raw_config, _) = plugin_api_ext("unconfigured", None);
+    assert_eq!(*raw_config, tauri_config);
+    assert_eq!(*raw_config, JsonValue::Null);
+  }
+
+  #[test]
+  fn update_config_reaches_swift_and_the_cache() {
+    let registered = Arc::new(Mutex::new(Vec::new()));
+
+    let bridge = ConfigBridge(registered.clone());
+    let (initial, updated) = with_plugin_api(
+      "updatable",
+      Some(json!({ "apiKey": "old", "retries": 3 })),
+      move |api: MockApi<DemoConfig>| {
+        let handle = api.register_swift_plugin_with_bridge(init_null_plugin, bridge).unwrap();
+        let initial = handle.parse_config::<DemoConfig>().unwrap();
+        handle
+          .update_config(json!({ "apiKey": "new", "retries": 5 }))
+          .unwrap();
+        (initial, handle.parse_config::<DemoConfig>().unwrap())
+      },
+    );
+    assert_eq!(initial.api_key, "old");
+    assert_eq!(
+      updated,
+      DemoConfig {
+        api_key: "new".into(),
+        retries: 5
+      }
+    );
+
+    let registered = registered.lock().unwrap();
+    assert_eq!(registered.len(), 2);
+    let (name, config) = &registered[1];
+    assert_eq!(name, "updatable");
+    let decoded: DemoConfig = serde_json::from_str(config).unwrap();
+    assert_eq!(decoded, updated);
+  }
+
+  #[test]
+  fn config_hooks_can_read_the_config() {
+    let observed = Arc::new(OnceLock::<PluginHandleExt<tauri::test::MockRuntime>>::new());
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let (observer, recorded) = (observed.clone(), seen.clone());
+    let plugin = MockSwiftPlugin::new().on_config_changed(move || {
+      let config = observer.get().unwrap().config();
+      recorded.lock().unwrap().push((*config).clone());
+    });
+    let handle = with_plugin_api("confighook", None, move |api: MockApi<JsonValue>| {
+      api.register_mock_swift_plugin(plugin).unwrap()
+    });
+    let _ = observed.set(handle.clone());
+
+    handle.update_config(json!({ "apiKey": "new" })).unwrap();
+    assert_eq!(*seen.lock().unwrap(), [json!({ "apiKey": "new" })]);
+  }
+
+  #[test]
+  fn webview_target_selects_labels() {
+    let labels = || ["settings", "main", "about"].map(String::from);
+
+    assert_eq!(WebviewTarget::First.select(labels()).unwrap(), ["about"]);
+    assert_eq!(WebviewTarget::Main.select(labels()).unwrap(), ["main"]);
+    assert_eq!(
+      WebviewTarget::from("settings").select(labels()).unwrap(),
+      ["settings"]
+    );
+    assert_eq!(
+      WebviewTarget::All.select(labels()).unwrap(),
+      ["about", "main", "settings"]
+    );
+    assert!(WebviewTarget::First.select([]).unwrap().is_empty());
+    assert!(matches!(
+      WebviewTarget::from("missing").select(labels()),
+      Err(PluginInvokeError::WebviewNotFound(label)) if label == "missing"
+    ));
+  }
+
+  #[test]
+  fn handle_remembers_the_webviews_it_was_loaded_into() {
+    let windows = ["settings", "main"];
+    let (_app, (bound, all, missing)) =
+      mock_app(&windows, "bound", None, |api: MockApi<JsonValue>| {
+        // each registration is unregistered so the next one can reuse the name
+        let register = |target: WebviewTarget| {
+          let handle =
+            api.register_swift_plugin_for_with_bridge(target, init_null_plugin, EchoBridge)?;
+          handle.unregister();
+          Ok::<_, PluginInvokeError>(handle.webview_labels().to_vec())
+        };
+        (
+          register("settings".into()).unwrap(),
+          register(WebviewTarget::All).unwrap(),
+          register("missing".into()).err().unwrap(),
+        )
+      });
+
+    assert_eq!(bound, ["settings"]);
+    assert_eq!(all, ["main", "settings"]);
+    assert!(matches!(missing, PluginInvokeError::WebviewNotFound(_)));
+  }
+
+  #[test]
+  fn plugins_registered_before_any_window_are_loaded_once_one_is_created() {
+    let loaded = Arc::new(Mutex::new(Vec::new()));
+    let record = |name: &'static str| {
+      let loaded = loaded.clone();
+      MockSwiftPlugin::new().on_load(move || loaded.lock().unwrap().push(name))
+    };
+    let (plugin, other) = (record("late"), record("late-other"));
+    let (app, labels) = mock_app(&[], "late", None, move |api: MockApi<JsonValue>| {
+      let handle = api.register_mock_swift_plugin(plugin).unwrap();
+      handle.webview_labels().to_vec()
+    });
+    // installs its own hook, which must not load "late" again
+    let plugin = tauri::plugin::Builder::<_, ()>::new("late-other")
+      .setup(move |_app, api| {
+        let api = PluginApiExt::new(api, "late-other");
+        api.register_mock_swift_plugin(other).unwrap();
+        Ok(())
+      })
+      .on_webview_ready(swift_webview_ready_handler("late-other"))
+      .build();
+    app.handle().plugin(plugin).unwrap();
+    assert!(labels.is_empty());
+    assert!(loaded.lock().unwrap().is_empty());
+
+    for label in ["main", "settings"] {
+      tauri::WebviewWindowBuilder::new(&app, label, Default::default())
+        .build()
+        .unwrap();
+    }
+    let mut loaded = loaded.lock().unwrap().clone();
+    loaded.sort();
+    assert_eq!(loaded, ["late", "late-other"]);
+  }
+
+  #[test]
+  fn waiting_for_webviews_fails_instead_of_hanging() {
+    let (tx, loaded) = oneshot::channel();
+    tx.send(()).unwrap();
+    assert!(wait_for_webviews(vec![loaded], true, Duration::ZERO).is_ok());
+
+    let (tx, dropped) = oneshot::channel::<()>();
+    drop(tx);
+    assert!(matches!(
+      wait_for_webviews(vec![dropped], false, Duration::from_secs(5)),
+      Err(PluginInvokeError::UnreachableWebview)
+    ));
+
+    let (_tx, queued) = oneshot::channel();
+    assert!(matches!(
+      wait_for_webviews(vec![queued], true, Duration::from_secs(5)),
+      Err(PluginInvokeError::WebviewUnresponsive)
+    ));
+
+    let (_tx, blocked) = oneshot::channel();
+    assert!(matches!(
+      wait_for_webviews(vec![blocked], false, Duration::from_millis(10)),
+      Err(PluginInvokeError::WebviewUnresponsive)
+    ));
+  }
+
+  #[test]
+  fn async_registration_makes_the_plugin_reachable() {
+    let handle = with_plugin_api("asyncregistered", None, |api: MockApi<JsonValue>| {
+      let registration = api.register_swift_plugin_for_with_bridge_async(
+        WebviewTarget::First,
+        init_null_plugin,
+        EchoBridge,
+      );
+      block_on(registration).unwrap()
+    });
+
+    assert!(registered_plugin("asyncregistered").is_some());
+    assert_eq!(handle.state(), PluginState::Loaded);
+    let response: JsonValue = handle.run_swift_plugin("ping", json!(1)).unwrap();
+    assert_eq!(response, json!(1));
+  }
+
+  #[test]
+  fn commands_wait_for_the_first_webview() {
+    let (app, handle) = mock_app(&[], "waiting", None, |api: MockApi<JsonValue>| {
+      api.register_swift_plugin_with_bridge(init_null_plugin, EchoBridge).unwrap()
+    });
+    assert_eq!(handle.state(), PluginState::Registered);
+
+    let mut ready = handle.await_ready().boxed();
+    let mut first = handle.run_swift_plugin_async::<JsonValue>("first", json!(1)).boxed();
+    let second = handle.run_swift_plugin_async::<JsonValue>("second", json!(2));
+    assert!((&mut ready).now_or_never().is_none());
+    assert!((&mut first).now_or_never().is_none());
+
+    tauri::WebviewWindowBuilder::new(&app, "main", Default::default())
+      .build()
+      .unwrap();
+    assert!(block_on(ready).is_ok());
+    assert_eq!(handle.state(), PluginState::Loaded);
+    assert_eq!(block_on(first).unwrap(), json!(1));
+    assert_eq!(block_on(second).unwrap(), json!(2));
+  }
+
+  #[test]
+  fn buffered_commands_time_out() {
+    let (_app, handle) = mock_app(&[], "neverloaded", None, |api: MockApi<JsonValue>| {
+      api.register_swift_plugin_with_bridge(init_null_plugin, EchoBridge).unwrap()
+    });
+    handle.set_command_buffer(CommandBuffer::new().timeout(Duration::from_millis(10)));
+
+    let error = handle.run_swift_plugin::<JsonValue>("ping", json!(1)).unwrap_err();
+    assert!(matches!(error.inner(), PluginInvokeError::NotLoaded(_)));
+    assert_eq!(error.context().unwrap().command(), "ping");
+  }
+
+  #[test]
+  fn dropped_calls_leave_the_command_buffer() {
+    let (_app, handle) = mock_app(&[], "dropbuffered", None, |api: MockApi<JsonValue>| {
+      api.register_swift_plugin_with_bridge(init_null_plugin, EchoBridge).unwrap()
+    });
+    handle.set_command_buffer(CommandBuffer::new().limit(1));
+
+    let mut first = handle.run_swift_plugin_async::<JsonValue>("first", json!(1)).boxed();
+    assert!((&mut first).now_or_never().is_none());
+    drop(first);
+    let mut second = handle.run_swift_plugin_async::<JsonValue>("second", json!(2)).boxed();
+    assert!((&mut second).now_or_never().is_none());
+  }
+
+  #[test]
+  fn blocking_calls_fail_on_the_main_thread_until_loaded() {
+    let (app, handle) = mock_app(&[], "mainthread", None, |api: MockApi<JsonValue>| {
+      api.register_swift_plugin_with_bridge(init_null_plugin, EchoBridge).unwrap()
+    });
+
+    assert!(handle.ensure_can_block("ping", false).is_ok());
+    let error = handle.ensure_can_block("ping", true).unwrap_err();
+    assert!(matches!(error.inner(), PluginInvokeError::WouldBlockMainThread));
+    assert_eq!(error.context().unwrap().command(), "ping");
+
+    tauri::WebviewWindowBuilder::new(&app, "main", Default::default()).build().unwrap();
+    assert_eq!(handle.state(), PluginState::Loaded);
+    assert!(handle.ensure_can_block("ping", true).is_ok());
+  }
+
+  #[test]
+  fn unregister_rejects_pending_calls_and_closes_channels() {
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let bridge = ConfigBridge(events.clone());
+    let (_app, handle) = mock_app(&["main"], "unloading", None, |api: MockApi<JsonValue>| {
+      api.register_swift_plugin_with_bridge(init_null_plugin, bridge).unwrap()
+    });
+    let pending = handle.run_swift_plugin_async::<JsonValue>("ping", json!({}));
+    let (_channel, mut receiver) = handle.open_channel::<JsonValue>();
+
+    handle.unregister();
+    assert_eq!(handle.state(), PluginState::Unloaded);
+    let error = block_on(pending).unwrap_err();
+    assert!(matches!(error.inner(), PluginInvokeError::PluginUnloaded));
+    assert!(receiver.recv().is_none());
+    let unregistered = ("unloading".to_string(), String::new());
+    assert_eq!(events.lock().unwrap().last(), Some(&unregistered));
+
+    let error = handle.run_swift_plugin::<JsonValue>("ping", json!({})).unwrap_err();
+    assert!(matches!(error.inner(), PluginInvokeError::PluginUnloaded));
+    assert!(block_on(handle.await_ready()).is_err());
+
+    // unregistering twice does not reach Swift again
+    handle.unregister();
+    assert_eq!(events.lock().unwrap().len(), 2);
+  }
+
+  #[test]
+  fn reload_registers_a_new_instance() {
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let bridge = ConfigBridge(events.clone());
+    let config = json!({ "apiKey": "secret" });
+    let (_app, handle) = mock_app(&["main"], "reloading", Some(config), |api: MockApi<JsonValue>| {
+      api.register_swift_plugin_with_bridge(init_null_plugin, bridge).unwrap()
+    });
+    let pending = handle.run_swift_plugin_async::<JsonValue>("ping", json!({}));
+
+    handle.reload(init_null_plugin).unwrap();
+    assert_eq!(handle.state(), PluginState::Loaded);
+    let error = block_on(pending).unwrap_err();
+    assert!(matches!(error.inner(), PluginInvokeError::PluginUnloaded));
+    let registered = ("reloading".to_string(), r#"{"apiKey":"secret"}"#.to_string());
+    assert_eq!(
+      *events.lock().unwrap(),
+      [registered.clone(), ("reloading".to_string(), String::new()), registered]
+    );
+
+    handle.unregister();
+    handle.reload(init_null_plugin).unwrap();
+    assert_eq!(handle.state(), PluginState::Loaded);
+    assert!(registered_plugin("reloading").is_some());
+  }
+
+  #[test]
+  fn abandoned_registrations_release_the_name() {
+    let (app, (abandoned, tracked, replacement)) =
+      mock_app(&["main"], "abandoned", None, |api: MockApi<JsonValue>| {
+        let abandoned = api.register_mock_swift_plugin(MockSwiftPlugin::new()).unwrap();
+        abandoned.abandon();
+        let tracked = api.app().swift_plugin("abandoned").is_some();
+        (abandoned, tracked, api.register_mock_swift_plugin(MockSwiftPlugin::new()))
+      });
+
+    assert_eq!(abandoned.state(), PluginState::Unloaded);
+    assert!(!tracked);
+    let replacement = replacement.unwrap();
+    assert!(app.swift_plugin("abandoned").unwrap().same_plugin(&replacement));
+
+    // abandoning a replaced registration leaves the replacement in place
+    abandoned.abandon();
+    assert!(registered_plugin("abandoned").is_some());
+    assert!(app.swift_plugin("abandoned").is_some());
+  }
+
+  #[test]
+  fn failed_reload_leaves_the_plugin_unloaded() {
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let bridge = ConfigBridge(events.clone());
+    let (app, handle) = mock_app(&["main"], "failedreload", None, |api: MockApi<JsonValue>| {
+      api.register_swift_plugin_with_bridge(init_null_plugin, bridge).unwrap()
+    });
+    let pending = handle.run_swift_plugin_async::<JsonValue>("ping", json!({}));
+    app.get_webview("main").unwrap().close().unwrap();
+
+    let result = handle.reload(init_null_plugin);
+    assert!(matches!(result, Err(PluginInvokeError::WebviewNotFound(label)) if label == "main"));
+    assert_eq!(handle.state(), PluginState::Unloaded);
+    let error = block_on(pending).unwrap_err();
+    assert!(matches!(error.inner(), PluginInvokeError::PluginUnloaded));
+    let events = events.lock().unwrap();
+    let unregistered = ("failedreload".to_string(), String::new());
+    assert_eq!(events.last(), Some(&unregistered));
+    // the new instance never reached Swift
+    assert_eq!(events.iter().filter(|(_, config)| !config.is_empty()).count(), 1);
+  }
 }
@@ -0,0 +1,281 @@
+//! Forwards `plugin:<name>|<command>` IPC calls from JavaScript to Swift plugins.
+
+use serde_json::Value as JsonValue;
+use tauri::{
+  ipc::{Invoke, InvokeBody},
+  Runtime,
+};
+
+use crate::{
+  bridge::SwiftBridge,
+  channel::{keep_js_channels, load_js_channels, release_js_channel},
+  desktop::{registered_plugin, run_command},
+  PluginInvokeError,
+};
+
+/// Creates an invoke handler that forwards the commands of `plugin` to its Swift implementation.
+///
+/// Install it on the Tauri plugin builder so `invoke('plugin:<name>|<command>')` reaches the Swift
+/// method of the same name without a Rust command per method. Like Tauri's mobile plugins, snake case
+/// command names are converted to the lower camel case Swift selector.
+///
+/// ```rust,ignore
+/// tauri::plugin::Builder::new("myplugin")
+///   .invoke_handler(tauri_swift_runtime::swift_invoke_handler("myplugin"))
+///   .setup(|_app, api| {
+///     PluginApiExt::new(api, "myplugin").register_swift_plugin(init_my_plugin)?;
+///     Ok(())
+///   })
+///   .on_webview_ready(tauri_swift_runtime::swift_webview_ready_handler("myplugin"))
+///   .build()
+/// ```
+///
+/// `Channel` arguments are forwarded too, so the Swift plugin can send data back to the frontend
+/// through them until the command settles. The channels of a `registerListener` call the Swift
+/// plugin resolves stay registered until the matching `removeListener` or until the window is
+/// destroyed.
+///
+/// The promise resolves with the value the Swift plugin resolves the invoke with. A rejection is
+/// forwarded as `{ code, message, ...data }`. Raw request bodies are rejected, since Swift plugins
+/// parse their arguments from JSON. Commands are not handled until the Swift plugin is registered,
+/// and are buffered until it is loaded into a webview.
+pub fn swift_invoke_handler<R: Runtime>(
+  plugin: impl Into<String>,
+) -> impl Fn(Invoke<R>) -> bool + Send + Sync + 'static {
+  let plugin = plugin.into();
+
+  move |invoke| {
+    let Some(registered) = registered_plugin(&plugin) else {
+      return false;
+    };
+
+    let resolver = invoke.resolver;
+    let payload = match json_payload(invoke.message.payload()) {
+      Ok(payload) => payload,
+      Err(e) => {
+        resolver.reject(e);
+        return true;
+      }
+    };
+    let webview = invoke.message.webview();
+    let channels = load_js_channels(&payload, &webview);
+    let selector = heck::AsLowerCamelCase(invoke.message.command()).to_string();
+    if let Some(id) = payload["channelId"].as_u64().filter(|_| selector == "removeListener") {
+      release_js_channel(webview.label(), id);
+    }
+    // A listener keeps sending through its channel after the command settled.
+    let keep_channels = selector == "registerListener";
+
+    let resolver_ = resolver.clone();
+    let result = forward_command(
+      &**registered.bridge(),
+      &plugin,
+      invoke.message.command(),
+      payload,
+      move |response| {
+        if keep_channels && response.is_ok() {
+          keep_js_channels(&webview, channels);
+        } else {
+          drop(channels);
+        }
+        match response {
+          Ok(value) => resolver_.resolve(value),
+          Err(error) => resolver_.reject(error),
+        }
+      },
+    );
+    if let Err(e) = result {
+      resolver.reject(e);
+    }
+
+    true
+  }
+}
+
+fn json_payload(body: &InvokeBody) -> Result<JsonValue, String> {
+  match body {
+    InvokeBody::Json(payload) => Ok(payload.clone()),
+    InvokeBody::Raw(_) => {
+      Err("Swift plugins only accept JSON arguments, not raw request bodies".to_string())
+    }
+  }
+}
+
+fn forward_command<F: FnOnce(Result<JsonValue, PluginInvokeError>) + Send + 'static>(
+  bridge: &dyn SwiftBridge,
+  plugin: &str,
+  command: &str,
+  payload: JsonValue,
+  respond: F,
+) -> Result<i32, PluginInvokeError> {
+  let command = heck::AsLowerCamelCase(command).to_string();
+  run_command(bridge, plugin, command, payload, respond)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use crate::{
+    channel::is_registered,
+    mock::{MockInvoke, MockSwiftPlugin},
+    test_util::{mock_ipc_app, MockApi},
+  };
+
+  use serde_json::json;
+  use tauri::{test::MockRuntime, webview::InvokeRequest, Manager, WebviewWindow};
+
+  use std::sync::mpsc;
+
+  /// Resolves with the command and its arguments, or rejects with the arguments as data if the
+  /// command is `fail`.
+  fn echo(invoke: MockInvoke) {
+    let payload: JsonValue = invoke.parse_args().unwrap();
+    if invoke.command() == "fail" {
+      invoke.reject_with("failed", Some("echo"), Some(payload));
+    } else {
+      let response = json!({ "command": invoke.command(), "payload": payload });
+      invoke.resolve(response);
+    }
+  }
+
+  fn forward(command: &str, payload: JsonValue) -> Result<JsonValue, JsonValue> {
+    let (tx, rx) = mpsc::channel();
+    let plugin = ["myMethod", "getLocation", "fail"]
+      .into_iter()
+      .fold(MockSwiftPlugin::new(), |plugin, command| plugin.command(command, echo));
+    forward_command(&plugin, "echo", command, payload, move |response| {
+      let response = response.map_err(|e| serde_json::to_value(e).unwrap());
+      tx.send(response).unwrap()
+    })
+    .unwrap();
+    rx.try_recv().unwrap()
+  }
+
+  /// Sends `invoke('plugin:<plugin>|<command>', args)` from `webview`.
+  fn invoke(
+    webview: &WebviewWindow<MockRuntime>,
+    plugin: &str,
+    command: &str,
+    args: JsonValue,
+  ) -> Result<JsonValue, JsonValue> {
+    let request = InvokeRequest {
+      cmd: format!("plugin:{plugin}|{command}"),
+      callback: tauri::ipc::CallbackFn(0),
+      error: tauri::ipc::CallbackFn(1),
+      url: "tauri://localhost".parse().unwrap(),
+      body: InvokeBody::Json(args),
+      headers: Default::default(),
+      invoke_key: tauri::test::INVOKE_KEY.to_string(),
+    };
+    tauri::test::get_ipc_response(webview, request).map(|body| body.deserialize().unwrap())
+  }
+
+  #[test]
+  fn invoke_handler_forwards_to_the_registered_plugin() {
+    let plugin = MockSwiftPlugin::new()
+      .command("myMethod", echo)
+      .command("fail", echo);
+    let (app, _) = mock_ipc_app("ipcforward", &["my_method", "fail"], move |api: MockApi| {
+      api.register_mock_swift_plugin(plugin).unwrap()
+    });
+    let webview = app.get_webview_window("main").unwrap();
+
+    assert_eq!(
+      invoke(&webview, "ipcforward", "my_method", json!({ "arg1": "value" })),
+      Ok(json!({ "command": "myMethod", "payload": { "arg1": "value" } }))
+    );
+    assert_eq!(
+      invoke(&webview, "ipcforward", "fail", json!({ "retryAfter": 5 })),
+      Err(json!({ "code": "echo", "message": "failed", "retryAfter": 5 }))
+    );
+  }
+
+  #[test]
+  fn invoke_handler_ignores_unregistered_plugins() {
+    let (app, _) = mock_ipc_app("ipcunregistered", &["my_method"], |_| ());
+    let webview = app.get_webview_window("main").unwrap();
+
+    let error = invoke(&webview, "ipcunregistered", "my_method", json!({})).unwrap_err();
+    assert_eq!(error, json!("Command my_method not found"));
+  }
+
+  #[test]
+  fn invoke_handler_rejects_with_the_call_context() {
+    let (app, handle) = mock_ipc_app("ipcunloaded", &["my_method"], |api: MockApi| {
+      api.register_mock_swift_plugin(MockSwiftPlugin::new()).unwrap()
+    });
+    let webview = app.get_webview_window("main").unwrap();
+    handle.unregister();
+
+    let error = invoke(&webview, "ipcunloaded", "my_method", json!({})).unwrap_err();
+    let error = error.as_str().unwrap();
+    assert!(error.contains("ipcunloaded") && error.contains("myMethod"), "{error}");
+    assert!(error.contains(&PluginInvokeError::PluginUnloaded.to_string()), "{error}");
+  }
+
+  #[test]
+  fn invoke_handler_releases_channels_unless_a_listener_keeps_them() {
+    let plugin = MockSwiftPlugin::new()
+      .command("download", |invoke| invoke.resolve(()))
+      .command("registerListener", |invoke| {
+        let args: JsonValue = invoke.parse_args().unwrap();
+        if args["event"] == "denied" {
+          invoke.reject("not allowed");
+        } else {
+          invoke.resolve(());
+        }
+      });
+    let commands = ["download", "registerListener", "removeListener"];
+    let (app, _) = mock_ipc_app("ipcchannels", &commands, move |api: MockApi| {
+      api.register_mock_swift_plugin(plugin).unwrap()
+    });
+    let webview = app.get_webview_window("main").unwrap();
+    let (settled, denied, listener) = (4_000_000_101, 4_000_000_102, 4_000_000_103);
+    let channel = |id: u64| format!("__CHANNEL__:{id}");
+
+    invoke(&webview, "ipcchannels", "download", json!({ "onProgress": channel(settled) })).unwrap();
+    assert!(!is_registered(settled));
+
+    let args = json!({ "event": "denied", "handler": channel(denied) });
+    invoke(&webview, "ipcchannels", "registerListener", args).unwrap_err();
+    assert!(!is_registered(denied));
+
+    let args = json!({ "event": "changed", "handler": channel(listener) });
+    invoke(&webview, "ipcchannels", "registerListener", args).unwrap();
+    assert!(is_registered(listener));
+
+    let args = json!({ "event": "changed", "channelId": listener });
+    invoke(&webview, "ipcchannels", "removeListener", args).unwrap();
+    assert!(!is_registered(listener));
+  }
+
+  #[test]
+  fn forwards_command_and_payload() {
+    assert_eq!(
+      forward("myMethod", json!({ "arg1": "value" })),
+      Ok(json!({ "command": "myMethod", "payload": { "arg1": "value" } }))
+    );
+  }
+
+  #[test]
+  fn converts_rust_command_names_to_swift_selectors() {
+    let response = forward("get_location", json!({})).unwrap();
+    assert_eq!(response["command"], "getLocation");
+  }
+
+  #[test]
+  fn rejects_raw_bodies() {
+    let payload = json!({ "arg1": "value" });
+    assert_eq!(json_payload(&InvokeBody::Json(payload.clone())), Ok(payload));
+    assert!(json_payload(&InvokeBody::Raw(vec![1, 2, 3])).is_err());
+  }
+
+  #[test]
+  fn rejects_with_error_response_code_message_and_data() {
+    assert_eq!(
+      forward("fail", json!({ "retryAfter": 5 })),
+      Err(json!({ "code": "echo", "message": "failed", "retryAfter": 5 }))
+    );
+  }
+}
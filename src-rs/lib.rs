@@ -1,7 +1,29 @@
+pub mod bridge;
+mod channel;
 mod desktop;
+mod ipc;
+mod lifecycle;
+mod listener;
+mod registry;
+#[cfg(any(target_os = "macos", target_os = "ios"))]
 mod macos;
+#[cfg(any(test, feature = "mock"))]
+pub mod mock;
+#[cfg(test)]
+mod test_util;
 
-pub use desktop::{PluginApiExt, PluginHandleExt, PluginInvokeError};
+pub use bridge::SwiftBridge;
+pub use channel::{ChannelArg, SwiftChannelReceiver};
+pub use desktop::{
+  CallContext, ErrorResponse, ErrorSnippet, PluginApiExt, PluginHandleExt, PluginInvokeError,
+  WebviewTarget, swift_run_event_handler, swift_webview_ready_handler,
+};
+pub use ipc::swift_invoke_handler;
+pub use lifecycle::{CommandBuffer, PluginState};
+pub use listener::{ListenerHandle, SwiftEventStream};
+pub use registry::{SwiftPluginInfo, SwiftPluginsExt};
+#[cfg(any(target_os = "macos", target_os = "ios"))]
+pub use macos::NativeBridge;
 
 #[doc(hidden)]
 pub use swift_rs;
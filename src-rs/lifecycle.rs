@@ -0,0 +1,451 @@
+//! Readiness of registered Swift plugins.
+//!
+//! A plugin is [`PluginState::Registered`] once Swift holds its instance and [`PluginState::Loaded`]
+//! once its `load(webview:)` ran. Commands sent in between are buffered and sent in order when it
+//! loads, so they never reach a half-initialized plugin. Once [`PluginState::Unloaded`], the plugin
+//! rejects every command until it is reloaded.
+
+use futures::{
+  channel::oneshot,
+  future::{self, Either},
+};
+use futures_timer::Delay;
+
+use std::{
+  collections::VecDeque,
+  fmt,
+  future::Future,
+  mem,
+  sync::{Arc, Mutex, MutexGuard, PoisonError},
+  time::Duration,
+};
+
+use crate::{
+  bridge::SwiftBridge,
+  channel::{ChannelArg, WeakChannelArg},
+  desktop::{complete_pending_call, remove_pending_calls, PendingPluginCallHandler},
+  PluginInvokeError,
+};
+
+/// The lifecycle state of a registered Swift plugin.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PluginState {
+  /// Swift holds the plugin instance, but has not loaded it into a webview yet.
+  ///
+  /// Commands are buffered until it is loaded.
+  Registered,
+  /// The plugin was loaded into a webview and runs commands.
+  Loaded,
+  /// The plugin was unloaded and rejects commands with [`PluginInvokeError::PluginUnloaded`].
+  Unloaded,
+}
+
+/// How long buffered commands wait for their plugin to load by default.
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Limits on the commands buffered while a plugin is not loaded yet.
+///
+/// By default any number of commands is buffered, and each fails with
+/// [`PluginInvokeError::NotLoaded`] if the plugin is not loaded within 10 seconds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CommandBuffer {
+  limit: Option<usize>,
+  timeout: Option<Duration>,
+}
+
+impl Default for CommandBuffer {
+  fn default() -> Self {
+    Self {
+      limit: None,
+      timeout: Some(DEFAULT_TIMEOUT),
+    }
+  }
+}
+
+impl CommandBuffer {
+  /// Creates a buffer with the default limits.
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Rejects commands with [`PluginInvokeError::CommandBufferFull`] once `limit` are buffered.
+  pub fn limit(mut self, limit: usize) -> Self {
+    self.limit = Some(limit);
+    self
+  }
+
+  /// Fails buffered commands with [`PluginInvokeError::NotLoaded`] if the plugin is not loaded
+  /// within `timeout` of sending them.
+  pub fn timeout(mut self, timeout: Duration) -> Self {
+    self.timeout = Some(timeout);
+    self
+  }
+
+  /// Keeps buffered commands until the plugin is loaded, however long that takes.
+  pub fn no_timeout(mut self) -> Self {
+    self.timeout = None;
+    self
+  }
+}
+
+struct QueuedCommand {
+  id: i32,
+  command: String,
+  payload: String,
+  // Dropped once the command leaves the buffer, which stops its timer.
+  _expiry: Option<oneshot::Sender<()>>,
+}
+
+struct Lifecycle {
+  state: PluginState,
+  flushing: bool,
+  buffer: CommandBuffer,
+  queued: VecDeque<QueuedCommand>,
+  waiters: Vec<oneshot::Sender<PluginState>>,
+  channels: Vec<WeakChannelArg>,
+}
+
+/// A plugin registered with Swift, with the commands waiting for it to load.
+pub(crate) struct RegisteredPlugin {
+  name: String,
+  bridge: Arc<dyn SwiftBridge>,
+  lifecycle: Mutex<Lifecycle>,
+}
+
+impl fmt::Debug for RegisteredPlugin {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let lifecycle = self.lifecycle();
+    f.debug_struct("RegisteredPlugin")
+      .field("name", &self.name)
+      .field("state", &lifecycle.state)
+      .field("queued", &lifecycle.queued.len())
+      .finish()
+  }
+}
+
+impl RegisteredPlugin {
+  pub(crate) fn new(name: impl Into<String>, bridge: Arc<dyn SwiftBridge>) -> Arc<Self> {
+    Arc::new(Self {
+      name: name.into(),
+      bridge,
+      lifecycle: Mutex::new(Lifecycle {
+        state: PluginState::Registered,
+        flushing: false,
+        buffer: CommandBuffer::default(),
+        queued: VecDeque::new(),
+        waiters: Vec::new(),
+        channels: Vec::new(),
+      }),
+    })
+  }
+
+  pub(crate) fn name(&self) -> &str {
+    &self.name
+  }
+
+  pub(crate) fn bridge(&self) -> &Arc<dyn SwiftBridge> {
+    &self.bridge
+  }
+
+  // Every update leaves the lifecycle consistent, so a panic while holding the lock does not
+  // corrupt it.
+  fn lifecycle(&self) -> MutexGuard<'_, Lifecycle> {
+    self.lifecycle.lock().unwrap_or_else(PoisonError::into_inner)
+  }
+
+  pub(crate) fn state(&self) -> PluginState {
+    self.lifecycle().state
+  }
+
+  pub(crate) fn command_buffer(&self) -> CommandBuffer {
+    self.lifecycle().buffer
+  }
+
+  pub(crate) fn set_command_buffer(&self, buffer: CommandBuffer) {
+    self.lifecycle().buffer = buffer;
+  }
+
+  /// Runs the command whose response handler is registered under `id`, buffering it until the
+  /// plugin is loaded.
+  pub(crate) fn send(
+    self: &Arc<Self>,
+    id: i32,
+    command: &str,
+    payload: String,
+  ) -> Result<(), PluginInvokeError> {
+    let mut lifecycle = self.lifecycle();
+    match lifecycle.state {
+      PluginState::Registered => {}
+      PluginState::Loaded => {
+        drop(lifecycle);
+        self.bridge.run_command(id, &self.name, command, &payload);
+        return Ok(());
+      }
+      PluginState::Unloaded => return Err(PluginInvokeError::PluginUnloaded),
+    }
+
+    if let Some(limit) = lifecycle.buffer.limit.filter(|&limit| lifecycle.queued.len() >= limit) {
+      return Err(PluginInvokeError::CommandBufferFull(limit));
+    }
+
+    let expiry = lifecycle.buffer.timeout.map(|timeout| {
+      let (expiry, stopped) = oneshot::channel::<()>();
+      let plugin = self.clone();
+      tauri::async_runtime::spawn(async move {
+        if let Either::Left(_) = future::select(Delay::new(timeout), stopped).await {
+          plugin.expire(id, timeout);
+        }
+      });
+      expiry
+    });
+    lifecycle.queued.push_back(QueuedCommand {
+      id,
+      command: command.to_string(),
+      payload,
+      _expiry: expiry,
+    });
+    Ok(())
+  }
+
+  fn expire(&self, id: i32, timeout: Duration) {
+    if self.dequeue(id) {
+      complete_pending_call(id, Err(PluginInvokeError::NotLoaded(timeout)));
+    }
+  }
+
+  /// Removes the buffered command `id`, so it is never sent. Returns whether it was buffered.
+  pub(crate) fn dequeue(&self, id: i32) -> bool {
+    let mut lifecycle = self.lifecycle();
+    match lifecycle.queued.iter().position(|command| command.id == id) {
+      Some(index) => lifecycle.queued.remove(index).is_some(),
+      None => false,
+    }
+  }
+
+  /// Marks the plugin as loaded, sending the buffered commands in order first.
+  ///
+  /// Does nothing unless the plugin is [`PluginState::Registered`].
+  pub(crate) fn mark_loaded(&self) {
+    let mut lifecycle = self.lifecycle();
+    if lifecycle.state != PluginState::Registered || lifecycle.flushing {
+      return;
+    }
+
+    // The commands are sent without holding the lock, since the bridge may respond right away and
+    // the response handler may send another command. Those are buffered behind the ones being sent
+    // until the buffer is empty.
+    lifecycle.flushing = true;
+    loop {
+      let queued = mem::take(&mut lifecycle.queued);
+      if queued.is_empty() {
+        break;
+      }
+      drop(lifecycle);
+      for command in queued {
+        self
+          .bridge
+          .run_command(command.id, &self.name, &command.command, &command.payload);
+      }
+      lifecycle = self.lifecycle();
+    }
+    lifecycle.flushing = false;
+
+    if lifecycle.state == PluginState::Registered {
+      self.set_state(lifecycle, PluginState::Loaded);
+    }
+  }
+
+  /// Closes `channel` when the plugin is unloaded or reloaded.
+  pub(crate) fn track_channel(&self, channel: ChannelArg) -> ChannelArg {
+    let mut lifecycle = self.lifecycle();
+    lifecycle.channels.retain(WeakChannelArg::is_alive);
+    lifecycle.channels.push(channel.downgrade());
+    channel
+  }
+
+  /// Marks the plugin as unloaded, rejecting its buffered and pending commands and closing its
+  /// channels.
+  ///
+  /// Returns `false` if it was already unloaded.
+  pub(crate) fn mark_unloaded(&self) -> bool {
+    let mut lifecycle = self.lifecycle();
+    if lifecycle.state == PluginState::Unloaded {
+      return false;
+    }
+    lifecycle.queued.clear();
+    let channels = mem::take(&mut lifecycle.channels);
+    let calls = remove_pending_calls(&self.name, |_| false);
+    self.set_state(lifecycle, PluginState::Unloaded);
+
+    close(channels, calls);
+    true
+  }
+
+  /// Puts the plugin back to [`PluginState::Registered`] while a new instance replaces it.
+  ///
+  /// The commands sent to the old instance are rejected and its channels closed, while buffered
+  /// commands wait for the new instance.
+  pub(crate) fn mark_reloading(&self) {
+    let mut lifecycle = self.lifecycle();
+    lifecycle.state = PluginState::Registered;
+    let channels = mem::take(&mut lifecycle.channels);
+    let queued = &lifecycle.queued;
+    let calls = remove_pending_calls(&self.name, |id| queued.iter().any(|command| command.id == id));
+    drop(lifecycle);
+
+    close(channels, calls);
+  }
+
+  fn set_state(&self, mut lifecycle: MutexGuard<'_, Lifecycle>, state: PluginState) {
+    lifecycle.state = state;
+    let waiters = mem::take(&mut lifecycle.waiters);
+    drop(lifecycle);
+    for waiter in waiters {
+      let _ = waiter.send(state);
+    }
+  }
+
+  /// Resolves once the plugin is loaded, or fails if it is unloaded first.
+  pub(crate) fn ready(&self) -> impl Future<Output = Result<(), PluginInvokeError>> + Send + 'static {
+    let mut lifecycle = self.lifecycle();
+    let waiter = match lifecycle.state {
+      PluginState::Registered => {
+        let (tx, rx) = oneshot::channel();
+        lifecycle.waiters.push(tx);
+        Ok(rx)
+      }
+      state => Err(state),
+    };
+
+    async move {
+      let state = match waiter {
+        Ok(rx) => rx.await.unwrap_or(PluginState::Unloaded),
+        Err(state) => state,
+      };
+      match state {
+        PluginState::Loaded => Ok(()),
+        _ => Err(PluginInvokeError::PluginUnloaded),
+      }
+    }
+  }
+}
+
+fn close(channels: Vec<WeakChannelArg>, calls: Vec<PendingPluginCallHandler>) {
+  for channel in channels {
+    channel.close();
+  }
+  for call in calls {
+    call(Err(PluginInvokeError::PluginUnloaded));
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use futures::{executor::block_on, FutureExt};
+
+  use crate::mock::MockSwiftPlugin;
+
+  type Sent = Arc<Mutex<Vec<String>>>;
+
+  /// Creates a plugin whose commands are recorded without a response.
+  fn plugin() -> (Arc<RegisteredPlugin>, Sent) {
+    let sent = Sent::default();
+    let bridge = ["first", "second", "third"]
+      .into_iter()
+      .fold(MockSwiftPlugin::new(), |bridge, command| {
+        let sent = sent.clone();
+        bridge.command(command, move |invoke| sent.lock().unwrap().push(invoke.command().into()))
+      });
+    (RegisteredPlugin::new("buffered", Arc::new(bridge)), sent)
+  }
+
+  fn sent(sent: &Sent) -> Vec<String> {
+    sent.lock().unwrap().clone()
+  }
+
+  #[test]
+  fn commands_are_buffered_until_loaded_and_sent_in_order() {
+    let (plugin, bridge) = plugin();
+    plugin.send(1, "first", "{}".into()).unwrap();
+    plugin.send(2, "second", "{}".into()).unwrap();
+    assert!(sent(&bridge).is_empty());
+
+    plugin.mark_loaded();
+    assert_eq!(sent(&bridge), ["first", "second"]);
+    assert_eq!(plugin.state(), PluginState::Loaded);
+
+    plugin.send(3, "third", "{}".into()).unwrap();
+    assert_eq!(sent(&bridge), ["first", "second", "third"]);
+  }
+
+  #[test]
+  fn full_buffer_rejects_commands() {
+    let (plugin, bridge) = plugin();
+    plugin.set_command_buffer(CommandBuffer::new().limit(1));
+    plugin.send(1, "first", "{}".into()).unwrap();
+    assert!(matches!(
+      plugin.send(2, "second", "{}".into()),
+      Err(PluginInvokeError::CommandBufferFull(1))
+    ));
+
+    plugin.mark_loaded();
+    assert_eq!(sent(&bridge), ["first"]);
+  }
+
+  #[test]
+  fn dequeued_commands_are_not_sent() {
+    let (plugin, bridge) = plugin();
+    plugin.send(1, "first", "{}".into()).unwrap();
+    plugin.send(2, "second", "{}".into()).unwrap();
+
+    assert!(plugin.dequeue(1));
+    assert!(!plugin.dequeue(1));
+    plugin.mark_loaded();
+    assert_eq!(sent(&bridge), ["second"]);
+  }
+
+  #[test]
+  fn ready_resolves_once_loaded() {
+    let (plugin, _) = plugin();
+    let mut ready = plugin.ready().boxed();
+    assert!((&mut ready).now_or_never().is_none());
+
+    plugin.mark_loaded();
+    assert!(block_on(ready).is_ok());
+    assert!(block_on(plugin.ready()).is_ok());
+  }
+
+  #[test]
+  fn unloaded_plugin_rejects_commands() {
+    let (plugin, bridge) = plugin();
+    let ready = plugin.ready();
+    plugin.send(1, "first", "{}".into()).unwrap();
+
+    assert!(plugin.mark_unloaded());
+    assert!(!plugin.mark_unloaded());
+    assert!(block_on(ready).is_err());
+    assert!(matches!(
+      plugin.send(2, "second", "{}".into()),
+      Err(PluginInvokeError::PluginUnloaded)
+    ));
+
+    plugin.mark_loaded();
+    assert_eq!(plugin.state(), PluginState::Unloaded);
+    assert!(sent(&bridge).is_empty());
+  }
+
+  #[test]
+  fn reloading_keeps_buffered_commands() {
+    let (plugin, bridge) = plugin();
+    plugin.mark_loaded();
+    plugin.mark_reloading();
+    plugin.send(1, "first", "{}".into()).unwrap();
+    assert!(sent(&bridge).is_empty());
+
+    plugin.mark_reloading();
+    plugin.mark_loaded();
+    assert_eq!(sent(&bridge), ["first"]);
+  }
+}
@@ -0,0 +1,252 @@
+//! Rust listeners for the events Swift plugins publish with `Plugin.trigger`.
+//!
+//! A listener is a channel registered through the plugin's built-in `registerListener` command. Dropping
+//! its handle sends the matching `removeListener` command.
+
+use futures::{executor::block_on, Stream, StreamExt};
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value as JsonValue};
+
+use std::{
+  fmt,
+  pin::Pin,
+  sync::Arc,
+  task::{Context, Poll},
+  time::Duration,
+};
+
+use crate::{
+  bridge::SwiftBridge,
+  channel::{create_channel, open_channel, ChannelArg, SwiftChannelReceiver},
+  desktop::{call_command, run_command},
+  PluginInvokeError,
+};
+
+/// A listener registered for a Swift plugin event.
+///
+/// The plugin stops sending the event to it when the handle is dropped.
+pub struct ListenerHandle {
+  bridge: Arc<dyn SwiftBridge>,
+  plugin: String,
+  event: String,
+  channel: ChannelArg,
+}
+
+impl ListenerHandle {
+  /// The event this listener receives.
+  pub fn event(&self) -> &str {
+    &self.event
+  }
+
+  pub(crate) fn channel(&self) -> &ChannelArg {
+    &self.channel
+  }
+}
+
+impl fmt::Debug for ListenerHandle {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("ListenerHandle")
+      .field("plugin", &self.plugin)
+      .field("event", &self.event)
+      .field("channel", &self.channel)
+      .finish()
+  }
+}
+
+impl Drop for ListenerHandle {
+  fn drop(&mut self) {
+    let payload = json!({ "event": self.event, "channelId": self.channel.id() });
+    let plugin = self.plugin.clone();
+    let event = self.event.clone();
+    let result = run_command(
+      &*self.bridge,
+      &self.plugin,
+      "removeListener",
+      payload,
+      move |response| {
+        if let Err(e) = response {
+          warn_removal_failed(&plugin, &event, &e);
+        }
+      },
+    );
+    if let Err(e) = result {
+      warn_removal_failed(&self.plugin, &self.event, &e);
+    }
+  }
+}
+
+fn warn_removal_failed(plugin: &str, event: &str, error: &PluginInvokeError) {
+  // An unloaded plugin already dropped its listeners, and nothing is listening once the app exits.
+  if !matches!(error.inner(), PluginInvokeError::PluginUnloaded | PluginInvokeError::ShuttingDown) {
+    log::warn!("failed to remove the `{event}` listener of plugin {plugin}: {error}");
+  }
+}
+
+/// The events of a Swift plugin, deserialized as `T`.
+///
+/// Use it as a [`Stream`] or block on [`Self::recv`]. The listener is removed when it is dropped.
+pub struct SwiftEventStream<T> {
+  receiver: SwiftChannelReceiver<T>,
+  handle: ListenerHandle,
+}
+
+impl<T> SwiftEventStream<T> {
+  /// The event this stream receives.
+  pub fn event(&self) -> &str {
+    self.handle.event()
+  }
+
+  pub(crate) fn channel(&self) -> &ChannelArg {
+    self.handle.channel()
+  }
+}
+
+impl<T> fmt::Debug for SwiftEventStream<T> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("SwiftEventStream")
+      .field("handle", &self.handle)
+      .finish()
+  }
+}
+
+impl<T: DeserializeOwned> SwiftEventStream<T> {
+  /// Blocks until the next event arrives.
+  pub fn recv(&mut self) -> Option<Result<T, PluginInvokeError>> {
+    self.receiver.recv()
+  }
+}
+
+impl<T: DeserializeOwned> Stream for SwiftEventStream<T> {
+  type Item = Result<T, PluginInvokeError>;
+
+  fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+    self.receiver.poll_next_unpin(cx)
+  }
+}
+
+pub(crate) fn listen<F: Fn(JsonValue) + Send + Sync + 'static>(
+  bridge: Arc<dyn SwiftBridge>,
+  plugin: &str,
+  event: &str,
+  timeout: Option<Duration>,
+  handler: F,
+) -> Result<ListenerHandle, PluginInvokeError> {
+  register_listener(bridge, plugin, event, timeout, create_channel(handler))
+}
+
+pub(crate) fn listen_stream<T: DeserializeOwned>(
+  bridge: Arc<dyn SwiftBridge>,
+  plugin: &str,
+  event: &str,
+  timeout: Option<Duration>,
+) -> Result<SwiftEventStream<T>, PluginInvokeError> {
+  let (channel, receiver) = open_channel();
+  let handle = register_listener(bridge, plugin, event, timeout, channel)?;
+  Ok(SwiftEventStream { receiver, handle })
+}
+
+fn register_listener(
+  bridge: Arc<dyn SwiftBridge>,
+  plugin: &str,
+  event: &str,
+  timeout: Option<Duration>,
+  channel: ChannelArg,
+) -> Result<ListenerHandle, PluginInvokeError> {
+  block_on(call_command::<JsonValue>(
+    &*bridge,
+    plugin,
+    "registerListener",
+    json!({ "event": event, "handler": channel }),
+    timeout,
+    None,
+  ))?;
+
+  Ok(ListenerHandle {
+    bridge,
+    plugin: plugin.to_string(),
+    event: event.to_string(),
+    channel,
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use crate::mock::MockSwiftPlugin;
+
+  use serde::Deserialize;
+
+  use std::sync::mpsc;
+
+  #[derive(Debug, PartialEq, Deserialize)]
+  struct Location {
+    lat: f64,
+  }
+
+  #[test]
+  fn listener_receives_triggered_events() {
+    let plugin = MockSwiftPlugin::new();
+    let (tx, rx) = mpsc::channel();
+    let handle = listen(Arc::new(plugin.clone()), "geo", "locationChanged", None, move |payload| {
+      tx.send(payload).unwrap()
+    })
+    .unwrap();
+
+    plugin.trigger("locationChanged", json!({ "lat": 1.0 })).unwrap();
+    plugin.trigger("other", json!({ "lat": 2.0 })).unwrap();
+
+    assert_eq!(rx.try_recv().unwrap(), json!({ "lat": 1.0 }));
+    assert!(rx.try_recv().is_err());
+    assert_eq!(handle.event(), "locationChanged");
+  }
+
+  #[test]
+  fn dropping_listener_removes_it() {
+    let plugin = MockSwiftPlugin::new();
+    let bridge = Arc::new(plugin.clone());
+    let (tx, rx) = mpsc::channel();
+    let handle = listen(bridge.clone(), "geo", "locationChanged", None, move |payload| {
+      tx.send(payload).unwrap()
+    })
+    .unwrap();
+    let other = listen(bridge, "geo", "locationChanged", None, |_| {}).unwrap();
+
+    drop(handle);
+    assert_eq!(plugin.listener_count("locationChanged"), 1);
+
+    plugin.trigger("locationChanged", json!({ "lat": 1.0 })).unwrap();
+    assert!(rx.try_recv().is_err());
+
+    drop(other);
+    assert_eq!(plugin.listener_count("locationChanged"), 0);
+  }
+
+  #[test]
+  fn event_stream_deserializes_events() {
+    let plugin = MockSwiftPlugin::new();
+    let mut events =
+      listen_stream::<Location>(Arc::new(plugin.clone()), "geo", "locationChanged", None).unwrap();
+
+    plugin.trigger("locationChanged", json!({ "lat": 1.0 })).unwrap();
+    plugin.trigger("locationChanged", json!({ "lng": 2.0 })).unwrap();
+
+    assert_eq!(events.recv().unwrap().unwrap(), Location { lat: 1.0 });
+    assert!(matches!(
+      events.recv(),
+      Some(Err(PluginInvokeError::CannotDeserializeResponse(_)))
+    ));
+
+    drop(events);
+    assert_eq!(plugin.listener_count("locationChanged"), 0);
+  }
+
+  #[test]
+  fn failed_registration_is_reported() {
+    let plugin =
+      MockSwiftPlugin::new().command("registerListener", |invoke| invoke.reject("not supported"));
+
+    let error = listen(Arc::new(plugin), "geo", "locationChanged", None, |_| {}).unwrap_err();
+    assert!(error.rejection().is_some());
+  }
+}
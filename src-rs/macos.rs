@@ -9,6 +9,8 @@ use std::{
   os::raw::{c_char, c_int, c_ulonglong},
 };
 
+use crate::bridge::{plugin_command_response_handler, send_channel_data_handler, SwiftBridge};
+
 type PluginMessageCallbackFn = unsafe extern "C" fn(c_int, c_int, *const c_char);
 pub struct PluginMessageCallback(pub PluginMessageCallbackFn);
 
@@ -45,4 +47,51 @@ swift!(pub fn swift_register_plugin(
   config: &SRString,
   webview: *const c_void
 ));
-swift!(pub fn swift_on_webview_created(webview: *const c_void, controller: *const c_void));
+swift!(pub fn swift_load_plugin(
+  name: &SRString,
+  webview: *const c_void,
+  controller: *const c_void
+));
+swift!(pub fn swift_update_plugin_config(name: &SRString, config: &SRString));
+swift!(pub fn swift_unregister_plugin(name: &SRString));
+
+/// The [`SwiftBridge`] backed by the `TauriSwiftRuntime` Swift package.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct NativeBridge;
+
+impl SwiftBridge for NativeBridge {
+  unsafe fn register_plugin(
+    &self,
+    name: &str,
+    plugin: *const c_void,
+    config: &str,
+    webview: *const c_void,
+  ) {
+    unsafe { swift_register_plugin(&name.into(), plugin, &config.into(), webview) }
+  }
+
+  fn run_command(&self, id: i32, plugin: &str, command: &str, payload: &str) {
+    unsafe {
+      swift_run_plugin_command(
+        id,
+        &plugin.into(),
+        &command.into(),
+        &payload.into(),
+        PluginMessageCallback(plugin_command_response_handler),
+        ChannelSendDataCallback(send_channel_data_handler),
+      )
+    }
+  }
+
+  unsafe fn load_plugin(&self, name: &str, webview: *const c_void, controller: *const c_void) {
+    unsafe { swift_load_plugin(&name.into(), webview, controller) }
+  }
+
+  fn update_config(&self, name: &str, config: &str) {
+    unsafe { swift_update_plugin_config(&name.into(), &config.into()) }
+  }
+
+  fn unregister_plugin(&self, name: &str) {
+    unsafe { swift_unregister_plugin(&name.into()) }
+  }
+}
@@ -0,0 +1,515 @@
+//! In-process stand-in for Swift plugins.
+//!
+//! A [`MockSwiftPlugin`] dispatches commands to Rust closures the same way the Swift `PluginManager`
+//! dispatches them to plugin methods, so code built on [`PluginHandleExt`] can run on machines that
+//! cannot link the Swift package.
+//!
+//! ```rust,ignore
+//! use serde_json::json;
+//! use tauri_swift_runtime::mock::MockSwiftPlugin;
+//!
+//! let plugin = MockSwiftPlugin::new()
+//!   .command("ping", |invoke| invoke.resolve(json!({ "pong": true })))
+//!   .command("delete", |invoke| {
+//!     invoke.reject_with("busy", Some("EBUSY"), Some(json!({ "retryAfter": 5 })))
+//!   });
+//!
+//! let handle = api.register_mock_swift_plugin(plugin)?;
+//! ```
+
+use serde::{de::DeserializeOwned, Deserialize, Deserializer, Serialize};
+use serde_json::Value as JsonValue;
+use tauri::Runtime;
+
+use std::{
+  collections::HashMap,
+  ffi::c_void,
+  fmt,
+  sync::{Arc, Mutex},
+};
+
+use crate::{
+  bridge::{handle_plugin_response, send_channel_data, SwiftBridge},
+  channel::CHANNEL_PREFIX,
+  PluginApiExt, PluginHandleExt, PluginInvokeError,
+};
+
+type CommandHandler = Arc<dyn Fn(MockInvoke) + Send + Sync + 'static>;
+type HookHandler = Arc<dyn Fn() + Send + Sync + 'static>;
+
+#[derive(Default)]
+struct MockPluginInner {
+  commands: Mutex<HashMap<String, CommandHandler>>,
+  config: Mutex<Option<String>>,
+  on_load: Mutex<Option<HookHandler>>,
+  on_config_changed: Mutex<Option<HookHandler>>,
+  on_unload: Mutex<Option<HookHandler>>,
+  listeners: Mutex<HashMap<String, Vec<MockChannel>>>,
+}
+
+/// A Swift plugin implemented with Rust closures.
+///
+/// Clones share the same commands, config and listeners.
+#[derive(Clone, Default)]
+pub struct MockSwiftPlugin {
+  inner: Arc<MockPluginInner>,
+}
+
+impl fmt::Debug for MockSwiftPlugin {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let mut commands: Vec<String> = self.inner.commands.lock().unwrap().keys().cloned().collect();
+    commands.sort();
+    f.debug_struct("MockSwiftPlugin")
+      .field("commands", &commands)
+      .finish()
+  }
+}
+
+impl MockSwiftPlugin {
+  /// Creates a plugin without commands.
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Handles `name` with the given closure.
+  ///
+  /// The closure owns the invoke and may resolve or reject it later, from any thread.
+  /// Registering `checkPermissions`, `requestPermissions`, `registerListener` or `removeListener`
+  /// overrides the built-in implementation, like overriding the method on a Swift `Plugin`.
+  pub fn command<F: Fn(MockInvoke) + Send + Sync + 'static>(
+    self,
+    name: impl Into<String>,
+    handler: F,
+  ) -> Self {
+    self
+      .inner
+      .commands
+      .lock()
+      .unwrap()
+      .insert(name.into(), Arc::new(handler));
+    self
+  }
+
+  /// Calls `handler` when the plugin is loaded into a webview, like overriding `load(webview:)`.
+  pub fn on_load<F: Fn() + Send + Sync + 'static>(self, handler: F) -> Self {
+    *self.inner.on_load.lock().unwrap() = Some(Arc::new(handler));
+    self
+  }
+
+  /// Calls `handler` after the Rust side updates the config, like overriding `onConfigChanged`.
+  pub fn on_config_changed<F: Fn() + Send + Sync + 'static>(self, handler: F) -> Self {
+    *self.inner.on_config_changed.lock().unwrap() = Some(Arc::new(handler));
+    self
+  }
+
+  /// Calls `handler` when the plugin is unregistered, like overriding `unload`.
+  pub fn on_unload<F: Fn() + Send + Sync + 'static>(self, handler: F) -> Self {
+    *self.inner.on_unload.lock().unwrap() = Some(Arc::new(handler));
+    self
+  }
+
+  /// Deserializes the current config of the plugin.
+  ///
+  /// Returns `None` if the plugin has not been registered yet.
+  pub fn parse_config<T: DeserializeOwned>(&self) -> Option<serde_json::Result<T>> {
+    self
+      .inner
+      .config
+      .lock()
+      .unwrap()
+      .as_deref()
+      .map(serde_json::from_str)
+  }
+
+  /// Returns how many listeners are registered for `event`.
+  pub fn listener_count(&self, event: &str) -> usize {
+    self.inner.listeners.lock().unwrap().get(event).map_or(0, Vec::len)
+  }
+
+  /// Sends `data` to every listener registered for `event`.
+  pub fn trigger(&self, event: &str, data: impl Serialize) -> serde_json::Result<()> {
+    let listeners = self
+      .inner
+      .listeners
+      .lock()
+      .unwrap()
+      .get(event)
+      .cloned()
+      .unwrap_or_default();
+    for channel in listeners {
+      channel.send(&data)?;
+    }
+    Ok(())
+  }
+
+  fn call_hook(hook: &Mutex<Option<HookHandler>>) {
+    // Cloned first so the hook runs without the lock held.
+    let handler = hook.lock().unwrap().clone();
+    if let Some(handler) = handler {
+      handler();
+    }
+  }
+
+  fn register_listener(&self, invoke: MockInvoke) {
+    #[derive(Deserialize)]
+    struct RegisterListenerArgs {
+      event: String,
+      handler: MockChannel,
+    }
+
+    match invoke.parse_args::<RegisterListenerArgs>() {
+      Ok(args) => {
+        self
+          .inner
+          .listeners
+          .lock()
+          .unwrap()
+          .entry(args.event)
+          .or_default()
+          .push(args.handler);
+        invoke.resolve(());
+      }
+      Err(e) => invoke.reject(e.to_string()),
+    }
+  }
+
+  fn remove_listener(&self, invoke: MockInvoke) {
+    #[derive(Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    struct RemoveListenerArgs {
+      event: String,
+      channel_id: u64,
+    }
+
+    match invoke.parse_args::<RemoveListenerArgs>() {
+      Ok(args) => {
+        if let Some(listeners) = self.inner.listeners.lock().unwrap().get_mut(&args.event) {
+          listeners.retain(|channel| channel.id != args.channel_id);
+        }
+        invoke.resolve(());
+      }
+      Err(e) => invoke.reject(e.to_string()),
+    }
+  }
+}
+
+impl SwiftBridge for MockSwiftPlugin {
+  unsafe fn register_plugin(
+    &self,
+    _name: &str,
+    _plugin: *const c_void,
+    config: &str,
+    webview: *const c_void,
+  ) {
+    *self.inner.config.lock().unwrap() = Some(config.to_string());
+    if !webview.is_null() {
+      Self::call_hook(&self.inner.on_load);
+    }
+  }
+
+  unsafe fn load_plugin(&self, _name: &str, _webview: *const c_void, _controller: *const c_void) {
+    Self::call_hook(&self.inner.on_load);
+  }
+
+  fn update_config(&self, _name: &str, config: &str) {
+    *self.inner.config.lock().unwrap() = Some(config.to_string());
+    Self::call_hook(&self.inner.on_config_changed);
+  }
+
+  fn unregister_plugin(&self, _name: &str) {
+    self.inner.listeners.lock().unwrap().clear();
+    Self::call_hook(&self.inner.on_unload);
+  }
+
+  fn run_command(&self, id: i32, plugin: &str, command: &str, payload: &str) {
+    let invoke = MockInvoke {
+      id,
+      command: command.to_string(),
+      payload: payload.to_string(),
+    };
+
+    let handler = self.inner.commands.lock().unwrap().get(command).cloned();
+    if let Some(handler) = handler {
+      return handler(invoke);
+    }
+
+    match command {
+      "checkPermissions" | "requestPermissions" => invoke.resolve(()),
+      "registerListener" => self.register_listener(invoke),
+      "removeListener" => self.remove_listener(invoke),
+      _ => {
+        let mut available: Vec<String> = self.inner.commands.lock().unwrap().keys().cloned().collect();
+        available.sort();
+        invoke.reject(format!(
+          "No command {command} found for plugin {plugin}.\nAvailable selectors:\n{}",
+          available.join("\n")
+        ));
+      }
+    }
+  }
+}
+
+/// A command invocation received by a [`MockSwiftPlugin`], mirroring the Swift `Invoke` class.
+///
+/// Dropping it without resolving or rejecting leaves the caller waiting, as in Swift.
+pub struct MockInvoke {
+  id: i32,
+  command: String,
+  payload: String,
+}
+
+impl fmt::Debug for MockInvoke {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("MockInvoke")
+      .field("id", &self.id)
+      .field("command", &self.command)
+      .field("payload", &self.payload)
+      .finish()
+  }
+}
+
+impl MockInvoke {
+  /// The invoked command.
+  pub fn command(&self) -> &str {
+    &self.command
+  }
+
+  /// The raw JSON arguments.
+  pub fn raw_args(&self) -> &str {
+    &self.payload
+  }
+
+  /// Deserializes the arguments.
+  pub fn parse_args<T: DeserializeOwned>(&self) -> serde_json::Result<T> {
+    serde_json::from_str(&self.payload)
+  }
+
+  /// Resolves the invoke with the given data.
+  pub fn resolve(self, data: impl Serialize) {
+    match serde_json::to_string(&data) {
+      Ok(json) => handle_plugin_response(self.id, true, &json),
+      Err(e) => handle_plugin_response(self.id, false, &JsonValue::from(e.to_string()).to_string()),
+    }
+  }
+
+  /// Rejects the invoke with the given message.
+  pub fn reject(self, message: impl Into<String>) {
+    self.reject_with(message, None, None)
+  }
+
+  /// Rejects the invoke with a message, an optional error code and optional data.
+  ///
+  /// The fields of an object `data` are merged into the error payload, as Swift's `Invoke.reject` does.
+  pub fn reject_with(self, message: impl Into<String>, code: Option<&str>, data: Option<JsonValue>) {
+    let mut payload = serde_json::Map::new();
+    payload.insert("message".into(), message.into().into());
+    if let Some(code) = code {
+      payload.insert("code".into(), code.into());
+    }
+    if let Some(JsonValue::Object(data)) = data {
+      payload.extend(data);
+    }
+    handle_plugin_response(self.id, false, &JsonValue::Object(payload).to_string());
+  }
+}
+
+/// A channel argument received by a [`MockSwiftPlugin`], mirroring the Swift `Channel` class.
+///
+/// Deserializes from the `__CHANNEL__:<id>` string a channel is serialized to.
+#[derive(Debug, Clone)]
+pub struct MockChannel {
+  id: u64,
+}
+
+impl MockChannel {
+  /// The channel identifier.
+  pub fn id(&self) -> u64 {
+    self.id
+  }
+
+  /// Sends the given data through the channel.
+  pub fn send(&self, data: impl Serialize) -> serde_json::Result<()> {
+    send_channel_data(self.id, &serde_json::to_string(&data)?);
+    Ok(())
+  }
+}
+
+impl<'de> Deserialize<'de> for MockChannel {
+  fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+    let value = String::deserialize(deserializer)?;
+    value
+      .strip_prefix(CHANNEL_PREFIX)
+      .and_then(|id| id.parse().ok())
+      .map(|id| Self { id })
+      .ok_or_else(|| serde::de::Error::custom(format!("Invalid channel definition from {value}")))
+  }
+}
+
+unsafe fn init_mock_plugin() -> *const c_void {
+  std::ptr::null()
+}
+
+impl<R: Runtime, C: DeserializeOwned> PluginApiExt<R, C> {
+  /// Registers a [`MockSwiftPlugin`] in place of a Swift plugin.
+  pub fn register_mock_swift_plugin(
+    &self,
+    plugin: MockSwiftPlugin,
+  ) -> Result<PluginHandleExt<R>, PluginInvokeError> {
+    self.register_swift_plugin_with_bridge(init_mock_plugin, plugin)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use crate::desktop::call_command;
+
+  use futures::executor::block_on;
+  use serde_json::json;
+
+  use std::ptr::null;
+
+  fn run<T: DeserializeOwned>(
+    plugin: &MockSwiftPlugin,
+    command: &str,
+    payload: JsonValue,
+  ) -> Result<T, PluginInvokeError> {
+    block_on(call_command(plugin, "demo", command, payload, None, None))
+      .map_err(PluginInvokeError::into_inner)
+  }
+
+  #[test]
+  fn resolves_registered_command() {
+    let plugin = MockSwiftPlugin::new().command("add", |invoke| {
+      let args: JsonValue = invoke.parse_args().unwrap();
+      let sum = args["a"].as_i64().unwrap() + args["b"].as_i64().unwrap();
+      invoke.resolve(json!({ "sum": sum }));
+    });
+
+    let response: JsonValue = run(&plugin, "add", json!({ "a": 1, "b": 2 })).unwrap();
+    assert_eq!(response, json!({ "sum": 3 }));
+  }
+
+  #[test]
+  fn rejects_with_code_and_message() {
+    let plugin = MockSwiftPlugin::new().command("delete", |invoke| {
+      invoke.reject_with("busy", Some("EBUSY"), Some(json!({ "retryAfter": 5 })))
+    });
+
+    match run::<JsonValue>(&plugin, "delete", json!({})) {
+      Err(PluginInvokeError::InvokeRejected(e)) => {
+        assert_eq!(e.code.as_deref(), Some("EBUSY"));
+        assert_eq!(e.message.as_deref(), Some("busy"));
+        assert_eq!(e.data, json!({ "retryAfter": 5 }));
+      }
+      other => panic!("unexpected response: {other:?}"),
+    }
+  }
+
+  #[test]
+  fn rejects_unknown_command() {
+    let plugin = MockSwiftPlugin::new().command("ping", |invoke| invoke.resolve(()));
+
+    match run::<JsonValue>(&plugin, "pong", json!({})) {
+      Err(PluginInvokeError::InvokeRejected(e)) => {
+        let message = e.message.unwrap();
+        assert!(message.starts_with("No command pong found for plugin demo."));
+        assert!(message.contains("ping"));
+      }
+      other => panic!("unexpected response: {other:?}"),
+    }
+  }
+
+  #[test]
+  fn resolves_built_in_permission_commands() {
+    let plugin = MockSwiftPlugin::new();
+    let response: JsonValue = run(&plugin, "checkPermissions", json!({})).unwrap();
+    assert_eq!(response, JsonValue::Null);
+  }
+
+  #[test]
+  fn exposes_registered_config() {
+    let plugin = MockSwiftPlugin::new();
+    assert!(plugin.parse_config::<JsonValue>().is_none());
+
+    unsafe { plugin.register_plugin("demo", null(), r#"{"apiKey":"secret"}"#, null()) };
+    let config: JsonValue = plugin.parse_config().unwrap().unwrap();
+    assert_eq!(config, json!({ "apiKey": "secret" }));
+  }
+
+  #[test]
+  fn config_updates_call_the_hook() {
+    let (tx, rx) = std::sync::mpsc::channel();
+    let plugin = MockSwiftPlugin::new();
+    let observer = plugin.clone();
+    let plugin = plugin.on_config_changed(move || {
+      tx.send(observer.parse_config::<JsonValue>().unwrap().unwrap()).unwrap()
+    });
+
+    unsafe { plugin.register_plugin("demo", null(), r#"{"apiKey":"old"}"#, null()) };
+    assert!(rx.try_recv().is_err());
+
+    plugin.update_config("demo", r#"{"apiKey":"new"}"#);
+    assert_eq!(rx.try_recv().unwrap(), json!({ "apiKey": "new" }));
+  }
+
+  #[test]
+  fn unregistering_drops_listeners_and_calls_the_hook() {
+    let (tx, rx) = std::sync::mpsc::channel();
+    let plugin = MockSwiftPlugin::new().on_unload(move || tx.send(()).unwrap());
+    let channel: MockChannel = serde_json::from_value(json!("__CHANNEL__:42")).unwrap();
+    plugin
+      .inner
+      .listeners
+      .lock()
+      .unwrap()
+      .insert("changed".into(), vec![channel]);
+
+    assert_eq!(plugin.listener_count("changed"), 1);
+
+    plugin.unregister_plugin("demo");
+    rx.try_recv().unwrap();
+    assert_eq!(plugin.listener_count("changed"), 0);
+  }
+
+  #[test]
+  fn loading_into_a_webview_calls_the_hook() {
+    let (tx, rx) = std::sync::mpsc::channel();
+    let plugin = MockSwiftPlugin::new().on_load(move || tx.send(()).unwrap());
+
+    unsafe { plugin.register_plugin("demo", null(), "{}", null()) };
+    assert!(rx.try_recv().is_err());
+
+    unsafe { plugin.load_plugin("demo", null(), null()) };
+    rx.try_recv().unwrap();
+  }
+
+  #[test]
+  fn parses_channel_arguments() {
+    let channel: MockChannel = serde_json::from_value(json!("__CHANNEL__:42")).unwrap();
+    assert_eq!(channel.id(), 42);
+    assert!(serde_json::from_value::<MockChannel>(json!("42")).is_err());
+  }
+
+  #[test]
+  fn emits_to_channel_arguments() {
+    #[derive(Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    struct DownloadArgs {
+      on_progress: MockChannel,
+    }
+
+    let plugin = MockSwiftPlugin::new().command("download", |invoke| {
+      let args: DownloadArgs = invoke.parse_args().unwrap();
+      args.on_progress.send(json!({ "progress": 1.0 })).unwrap();
+      invoke.resolve(());
+    });
+
+    let (tx, rx) = std::sync::mpsc::channel();
+    let on_progress = crate::channel::create_channel(move |message| tx.send(message).unwrap());
+
+    run::<JsonValue>(&plugin, "download", json!({ "onProgress": on_progress })).unwrap();
+    assert_eq!(rx.try_recv().unwrap(), json!({ "progress": 1.0 }));
+  }
+}
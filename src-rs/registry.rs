@@ -0,0 +1,158 @@
+//! Lookup of the Swift plugins registered on an app.
+//!
+//! Every [`PluginHandleExt`] is tracked in the app state when it is registered, so other plugins and
+//! commands can reach it by name without managing it themselves.
+
+use tauri::{Manager, Runtime};
+
+use std::{
+  collections::BTreeMap,
+  sync::{Mutex, MutexGuard, PoisonError},
+};
+
+use crate::{PluginHandleExt, PluginState};
+
+struct SwiftPlugins<R: Runtime>(Mutex<BTreeMap<String, PluginHandleExt<R>>>);
+
+impl<R: Runtime> SwiftPlugins<R> {
+  fn handles(&self) -> MutexGuard<'_, BTreeMap<String, PluginHandleExt<R>>> {
+    self.0.lock().unwrap_or_else(PoisonError::into_inner)
+  }
+}
+
+pub(crate) fn track<R: Runtime>(handle: &PluginHandleExt<R>) {
+  let app = handle.app();
+  app.manage(SwiftPlugins::<R>(Default::default()));
+  app
+    .state::<SwiftPlugins<R>>()
+    .handles()
+    .insert(handle.name().to_string(), handle.clone());
+}
+
+/// Forgets `handle`, unless another plugin was registered under its name since.
+pub(crate) fn untrack<R: Runtime>(handle: &PluginHandleExt<R>) {
+  let Some(plugins) = handle.app().try_state::<SwiftPlugins<R>>() else {
+    return;
+  };
+  let mut handles = plugins.handles();
+  if handles.get(handle.name()).is_some_and(|tracked| tracked.same_plugin(handle)) {
+    handles.remove(handle.name());
+  }
+}
+
+/// A Swift plugin registered on the app, see [`SwiftPluginsExt::swift_plugins`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SwiftPluginInfo {
+  name: String,
+  state: PluginState,
+  webview_labels: Vec<String>,
+}
+
+impl SwiftPluginInfo {
+  /// The plugin name.
+  pub fn name(&self) -> &str {
+    &self.name
+  }
+
+  /// The lifecycle state of the plugin.
+  pub fn state(&self) -> PluginState {
+    self.state
+  }
+
+  /// The labels of the webviews the plugin was loaded into, see [`PluginHandleExt::webview_labels`].
+  pub fn webview_labels(&self) -> &[String] {
+    &self.webview_labels
+  }
+}
+
+/// Extensions to [`tauri::App`], [`tauri::AppHandle`] and the other [`Manager`]s to reach the Swift
+/// plugins registered on the app.
+pub trait SwiftPluginsExt<R: Runtime> {
+  /// Returns a handle to the Swift plugin registered as `name`.
+  ///
+  /// Unregistered plugins are still returned so they can be reloaded, check
+  /// [`PluginHandleExt::state`].
+  fn swift_plugin(&self, name: &str) -> Option<PluginHandleExt<R>>;
+
+  /// Lists the Swift plugins registered on the app, sorted by name.
+  fn swift_plugins(&self) -> Vec<SwiftPluginInfo>;
+}
+
+impl<R: Runtime, M: Manager<R>> SwiftPluginsExt<R> for M {
+  fn swift_plugin(&self, name: &str) -> Option<PluginHandleExt<R>> {
+    let plugins = self.try_state::<SwiftPlugins<R>>()?;
+    plugins.handles().get(name).cloned()
+  }
+
+  fn swift_plugins(&self) -> Vec<SwiftPluginInfo> {
+    let Some(plugins) = self.try_state::<SwiftPlugins<R>>() else {
+      return Vec::new();
+    };
+    plugins
+      .handles()
+      .values()
+      .map(|handle| SwiftPluginInfo {
+        name: handle.name().to_string(),
+        state: handle.state(),
+        webview_labels: handle.webview_labels().to_vec(),
+      })
+      .collect()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use crate::{
+    mock::MockSwiftPlugin,
+    test_util::{init_null_plugin, mock_app, MockApi},
+    PluginInvokeError,
+  };
+
+  #[test]
+  fn registered_plugins_are_reachable_from_the_app() {
+    let (app, _) = mock_app(&["main"], "registrylookup", None, |api: MockApi| {
+      api.register_mock_swift_plugin(MockSwiftPlugin::new()).unwrap()
+    });
+
+    let handle = app.swift_plugin("registrylookup").unwrap();
+    assert_eq!(handle.name(), "registrylookup");
+    assert!(app.handle().swift_plugin("missing").is_none());
+    assert_eq!(
+      app.swift_plugins(),
+      [SwiftPluginInfo {
+        name: "registrylookup".into(),
+        state: PluginState::Loaded,
+        webview_labels: vec!["main".into()],
+      }]
+    );
+
+    handle.unregister();
+    assert_eq!(app.swift_plugins()[0].state(), PluginState::Unloaded);
+  }
+
+  #[test]
+  fn duplicate_names_are_rejected_until_unregistered() {
+    let (_app, (first, duplicate, replacement)) =
+      mock_app(&["main"], "registryduplicate", None, |api: MockApi| {
+        let first = api.register_mock_swift_plugin(MockSwiftPlugin::new()).unwrap();
+        let duplicate = api.register_mock_swift_plugin(MockSwiftPlugin::new()).err();
+        first.unregister();
+        let replacement = api.register_mock_swift_plugin(MockSwiftPlugin::new());
+        (first, duplicate, replacement)
+      });
+
+    assert!(matches!(
+      duplicate,
+      Some(PluginInvokeError::AlreadyRegistered(name)) if name == "registryduplicate"
+    ));
+    let replacement = replacement.unwrap();
+    assert_eq!(replacement.state(), PluginState::Loaded);
+    assert!(matches!(
+      first.reload(init_null_plugin),
+      Err(PluginInvokeError::AlreadyRegistered(_))
+    ));
+    assert_eq!(first.state(), PluginState::Unloaded);
+  }
+}
@@ -0,0 +1,146 @@
+//! Fixtures shared by the unit tests.
+
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value as JsonValue};
+
+use tauri::utils::acl::ExecutionContext;
+
+use std::{
+  ffi::c_void,
+  sync::{mpsc, Arc, Mutex},
+};
+
+use crate::{
+  bridge::{handle_plugin_response, SwiftBridge},
+  swift_invoke_handler, swift_webview_ready_handler, PluginApiExt,
+};
+
+pub(crate) type MockApi<C = JsonValue> = PluginApiExt<tauri::test::MockRuntime, C>;
+
+/// Stands in for the function creating the Swift plugin instance, which the test bridges ignore.
+pub(crate) unsafe fn init_null_plugin() -> *const c_void {
+  std::ptr::null()
+}
+
+/// Resolves every command with its payload, or rejects it if the command is `fail`.
+pub(crate) struct EchoBridge;
+
+impl SwiftBridge for EchoBridge {
+  unsafe fn register_plugin(&self, _: &str, _: *const c_void, _: &str, _: *const c_void) {}
+
+  fn run_command(&self, id: i32, plugin: &str, command: &str, payload: &str) {
+    if command == "fail" {
+      let error = json!({ "code": plugin, "message": payload });
+      handle_plugin_response(id, false, &error.to_string());
+    } else {
+      handle_plugin_response(id, true, payload);
+    }
+  }
+}
+
+/// Records the config each plugin is registered or updated with, and an empty config for each
+/// plugin unregistered.
+#[derive(Default)]
+pub(crate) struct ConfigBridge(pub(crate) Arc<Mutex<Vec<(String, String)>>>);
+
+impl SwiftBridge for ConfigBridge {
+  unsafe fn register_plugin(&self, name: &str, _: *const c_void, config: &str, _: *const c_void) {
+    self.0.lock().unwrap().push((name.into(), config.into()));
+  }
+
+  fn run_command(&self, _: i32, _: &str, _: &str, _: &str) {}
+
+  fn update_config(&self, name: &str, config: &str) {
+    self.0.lock().unwrap().push((name.into(), config.into()));
+  }
+
+  fn unregister_plugin(&self, name: &str) {
+    self.0.lock().unwrap().push((name.into(), String::new()));
+  }
+}
+
+/// Runs `setup` with the API of a plugin registered on a mock app without windows or a
+/// [`swift_webview_ready_handler`].
+pub(crate) fn with_plugin_api<C, T, F>(name: &'static str, config: Option<JsonValue>, setup: F) -> T
+where
+  C: DeserializeOwned + Send + Sync + 'static,
+  T: Send + 'static,
+  F: FnOnce(MockApi<C>) -> T + Send + 'static,
+{
+  build_mock_app(&[], name, config, false, &[], setup).1
+}
+
+/// Builds a mock app with a window for each of the given labels, then adds a plugin that installs
+/// [`swift_invoke_handler`] and [`swift_webview_ready_handler`] and runs `setup` with its API.
+pub(crate) fn mock_app<C, T, F>(
+  windows: &[&str],
+  name: &'static str,
+  config: Option<JsonValue>,
+  setup: F,
+) -> (tauri::App<tauri::test::MockRuntime>, T)
+where
+  C: DeserializeOwned + Send + Sync + 'static,
+  T: Send + 'static,
+  F: FnOnce(MockApi<C>) -> T + Send + 'static,
+{
+  build_mock_app(windows, name, config, true, &[], setup)
+}
+
+/// Like [`mock_app`] with a `main` window, allowing JavaScript to invoke the given commands of the
+/// plugin.
+pub(crate) fn mock_ipc_app<T, F>(
+  name: &'static str,
+  commands: &[&str],
+  setup: F,
+) -> (tauri::App<tauri::test::MockRuntime>, T)
+where
+  T: Send + 'static,
+  F: FnOnce(MockApi) -> T + Send + 'static,
+{
+  build_mock_app(&["main"], name, None, true, commands, setup)
+}
+
+fn build_mock_app<C, T, F>(
+  windows: &[&str],
+  name: &'static str,
+  config: Option<JsonValue>,
+  webview_loader: bool,
+  commands: &[&str],
+  setup: F,
+) -> (tauri::App<tauri::test::MockRuntime>, T)
+where
+  C: DeserializeOwned + Send + Sync + 'static,
+  T: Send + 'static,
+  F: FnOnce(MockApi<C>) -> T + Send + 'static,
+{
+  let mut context = tauri::test::mock_context(tauri::test::noop_assets());
+  if let Some(config) = config {
+    context.config_mut().plugins.0.insert(name.into(), config);
+  }
+  for command in commands {
+    context
+      .runtime_authority_mut()
+      .__allow_command(format!("plugin:{name}|{command}"), ExecutionContext::Local);
+  }
+  let app = tauri::test::mock_builder().build(context).unwrap();
+  for label in windows {
+    tauri::WebviewWindowBuilder::new(&app, *label, Default::default())
+      .build()
+      .unwrap();
+  }
+
+  let (tx, rx) = mpsc::channel();
+  let mut plugin = tauri::plugin::Builder::<_, C>::new(name)
+    .invoke_handler(swift_invoke_handler(name))
+    .setup(move |_app, api| {
+      tx.send(setup(PluginApiExt::new(api, name))).unwrap();
+      Ok(())
+    });
+  if webview_loader {
+    plugin = plugin.on_webview_ready(swift_webview_ready_handler(name));
+  }
+  let plugin = plugin.build();
+  app.handle().plugin(plugin).unwrap();
+  let value = rx.try_recv().unwrap();
+  (app, value)
+}
@@ -32,6 +32,10 @@ open class Plugin: NSObject {
 
   @objc open func load(webview: WKWebView) {}
 
+  @objc open func onConfigChanged() {}
+
+  @objc open func unload() {}
+
   @objc open func checkPermissions(_ invoke: Invoke) {
     invoke.resolve()
   }
@@ -29,7 +29,9 @@ public class PluginManager {
   #elseif os(macOS)
   public var viewController: NSViewController?
   #endif
-  var plugins: [String: PluginHandle] = [:]
+  // Rust registers, reaches and unregisters plugins from any thread, so every access takes the lock.
+  private var plugins: [String: PluginHandle] = [:]
+  private let pluginsLock = NSLock()
   var ipcDispatchQueue = DispatchQueue(label: "ipc")
   public var isSimEnvironment: Bool {
     #if targetEnvironment(simulator)
@@ -47,14 +49,6 @@ public class PluginManager {
     return URL(string: "asset://localhost")!.appendingPathComponent(inputURL.path)
   }
 
-  func onWebviewCreated(_ webview: WKWebView) {
-    for (_, handle) in plugins {
-      if !handle.loaded {
-        handle.instance.load(webview: webview)
-      }
-    }
-  }
-
   func load<P: Plugin>(name: String, plugin: P, config: String, webview: WKWebView?) {
     plugin.setConfig(config)
     let handle = PluginHandle(plugin: plugin)
@@ -62,7 +56,58 @@ public class PluginManager {
       handle.instance.load(webview: webview)
       handle.loaded = true
     }
-    plugins[name] = handle
+    withPlugins { $0[name] = handle }
+  }
+
+  func plugin(named name: String) -> PluginHandle? {
+    return withPlugins { $0[name] }
+  }
+
+  private func withPlugins<T>(_ body: (inout [String: PluginHandle]) -> T) -> T {
+    pluginsLock.lock()
+    defer { pluginsLock.unlock() }
+    return body(&plugins)
+  }
+
+  func loadPlugin(name: String, webview: WKWebView, viewController: NSObject?) {
+    if let handle = plugin(named: name) {
+      if self.viewController == nil, let viewController = viewController {
+        setViewController(viewController)
+      }
+      handle.instance.load(webview: webview)
+      handle.loaded = true
+    }
+  }
+
+  func setViewController(_ viewController: NSObject) {
+    #if os(iOS)
+    if let vc = viewController as? UIViewController {
+      self.viewController = vc
+    }
+    #elseif os(macOS)
+    if let vc = viewController as? NSViewController {
+      self.viewController = vc
+    } else if let vc = (viewController as? NSWindow)?.contentViewController {
+      self.viewController = vc
+    }
+    #endif
+  }
+
+  func updateConfig(name: String, config: String) {
+    if let plugin = self.plugin(named: name) {
+      ipcDispatchQueue.async {
+        plugin.instance.setConfig(config)
+        plugin.instance.onConfigChanged()
+      }
+    }
+  }
+
+  func unregister(name: String) {
+    if let plugin = withPlugins({ $0.removeValue(forKey: name) }) {
+      ipcDispatchQueue.async {
+        plugin.instance.unload()
+      }
+    }
   }
 
   func decodeTypeEncoding(_ encoding: String) -> String {
@@ -81,7 +126,7 @@ public class PluginManager {
   }
 
   func invoke(name: String, invoke: Invoke) {
-    if let plugin = plugins[name] {
+    if let plugin = self.plugin(named: name) {
       ipcDispatchQueue.async {
         let selectorWithCompletionHandler = Selector(("\(invoke.command):completionHandler:"))
         let selectorWithThrows = Selector(("\(invoke.command):error:"))
@@ -179,18 +224,23 @@ func registerPlugin(name: SRString, plugin: NSObject, config: SRString, webview:
   )
 }
 
-@_cdecl("on_webview_created")
-func onWebviewCreated(webview: WKWebView, viewController: NSObject) {
-  #if os(iOS)
-  if let vc = viewController as? UIViewController {
-    PluginManager.shared.viewController = vc
-  }
-  #elseif os(macOS)
-  if let vc = viewController as? NSViewController {
-    PluginManager.shared.viewController = vc
-  }
-  #endif
-  PluginManager.shared.onWebviewCreated(webview)
+@_cdecl("swift_load_plugin")
+func loadPlugin(name: SRString, webview: WKWebView, viewController: NSObject?) {
+  PluginManager.shared.loadPlugin(
+    name: name.toString(),
+    webview: webview,
+    viewController: viewController
+  )
+}
+
+@_cdecl("swift_update_plugin_config")
+func updatePluginConfig(name: SRString, config: SRString) {
+  PluginManager.shared.updateConfig(name: name.toString(), config: config.toString())
+}
+
+@_cdecl("swift_unregister_plugin")
+func unregisterPlugin(name: SRString) {
+  PluginManager.shared.unregister(name: name.toString())
 }
 
 @_cdecl("swift_run_plugin_command")
@@ -0,0 +1,45 @@
+//! Shutting down is process wide, so it is tested in its own test binary.
+
+use futures::executor::block_on;
+use serde_json::{json, Value as JsonValue};
+use tauri::{test::MockRuntime, RunEvent};
+use tauri_swift_runtime::{mock::MockSwiftPlugin, PluginApiExt, PluginHandleExt, PluginInvokeError};
+
+use std::sync::mpsc;
+
+#[test]
+fn exiting_releases_pending_calls_and_channels() {
+  let app = tauri::test::mock_builder()
+    .build(tauri::test::mock_context(tauri::test::noop_assets()))
+    .unwrap();
+  tauri::WebviewWindowBuilder::new(&app, "main", Default::default())
+    .build()
+    .unwrap();
+
+  let (tx, rx) = mpsc::channel::<PluginHandleExt<MockRuntime>>();
+  let plugin = tauri::plugin::Builder::<_, ()>::new("busy")
+    .setup(move |_app, api| {
+      // Never responds, like a Swift plugin that is still busy when the app exits.
+      let plugin = MockSwiftPlugin::new().command("ping", |_invoke| {});
+      let handle = PluginApiExt::new(api, "busy").register_mock_swift_plugin(plugin).unwrap();
+      tx.send(handle).unwrap();
+      Ok(())
+    })
+    .build();
+  app.handle().plugin(plugin).unwrap();
+  let handle = rx.try_recv().unwrap();
+
+  let pending = handle.run_swift_plugin_async::<JsonValue>("ping", json!({}));
+  let (_channel, mut receiver) = handle.open_channel::<JsonValue>();
+
+  let mut on_event = tauri_swift_runtime::swift_run_event_handler();
+  on_event(app.handle(), &RunEvent::Exit);
+
+  let error = block_on(pending).unwrap_err();
+  assert!(matches!(error.inner(), PluginInvokeError::ShuttingDown));
+  assert_eq!(error.context().unwrap().command(), "ping");
+  assert!(receiver.recv().is_none());
+
+  let error = handle.run_swift_plugin::<JsonValue>("ping", json!({})).unwrap_err();
+  assert!(matches!(error.inner(), PluginInvokeError::ShuttingDown));
+}